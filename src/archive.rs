@@ -0,0 +1,324 @@
+//! Export of the whole keyspace to newline delimited JSON, and the import back.
+//!
+//! The first line is an `archive` record carrying the format version, then one
+//! line per header, topic, version, history list, fork, result and representative.
+
+use crate::{
+    error::DumpError,
+    model::Fork,
+    store::{Store, Write as StoreWrite},
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::{BufRead, Write};
+
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// set when an archive is imported, to when it was
+const IMPORTED: &str = "imported";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Record {
+    Archive {
+        version: u32,
+    },
+    Header {
+        id: String,
+        value: Value,
+    },
+    Topic {
+        hash: String,
+        value: Value,
+    },
+    Version {
+        hash: String,
+        value: Value,
+    },
+    /// oldest entry first, the way it is stored
+    History {
+        id: String,
+        entries: Vec<String>,
+    },
+    /// `children:{parent}` sets are rebuilt from these on import
+    Fork {
+        id: String,
+        value: Value,
+    },
+    Result {
+        hash: String,
+        value: Value,
+    },
+    Representative {
+        id: String,
+        value: Value,
+    },
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportSummary {
+    pub headers: usize,
+    pub topics: usize,
+    pub versions: usize,
+    pub histories: usize,
+    pub forks: usize,
+    pub results: usize,
+    pub representatives: usize,
+}
+
+async fn object(store: &dyn Store, key: &str) -> Result<Option<Value>, DumpError> {
+    match store.get(key).await? {
+        Some(slice) => Ok(Some(serde_json::from_slice(&slice)?)),
+        None => Ok(None),
+    }
+}
+
+/// Reads every record out of `store`.
+pub async fn export(store: &dyn Store) -> Result<Vec<Record>, DumpError> {
+    let mut records = vec![Record::Archive {
+        version: ARCHIVE_VERSION,
+    }];
+
+    let ids = store.set_members("headers").await?;
+
+    for id in &ids {
+        if let Some(value) = object(store, &format!("header:{}", id)).await? {
+            records.push(Record::Header {
+                id: id.to_string(),
+                value,
+            });
+        }
+    }
+
+    for hash in store.set_members("topics").await? {
+        if let Some(value) = object(store, &format!("topic:{}", hash)).await? {
+            records.push(Record::Topic { hash, value });
+        }
+    }
+
+    for hash in store.set_members("versions").await? {
+        if let Some(value) = object(store, &format!("version:{}", hash)).await? {
+            records.push(Record::Version { hash, value });
+        }
+    }
+
+    for id in ids {
+        let entries = store.list_range(&format!("history:{}", id), 0, -1).await?;
+        if !entries.is_empty() {
+            records.push(Record::History { id, entries });
+        }
+    }
+
+    for id in store.set_members("forks").await? {
+        if let Some(value) = object(store, &format!("fork:{}", id)).await? {
+            records.push(Record::Fork { id, value });
+        }
+    }
+
+    for hash in store.set_members("results").await? {
+        if let Some(value) = object(store, &format!("result:{}", hash)).await? {
+            records.push(Record::Result { hash, value });
+        }
+    }
+
+    for id in store.hash_keys("reps").await? {
+        if let Some(slice) = store.hash_get("reps", &id).await? {
+            records.push(Record::Representative {
+                id,
+                value: serde_json::from_slice(&slice)?,
+            });
+        }
+    }
+
+    Ok(records)
+}
+
+pub fn write_ndjson(records: &[Record], mut writer: impl Write) -> std::io::Result<()> {
+    for record in records {
+        serde_json::to_writer(&mut writer, record)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
+
+pub fn read_ndjson(reader: impl BufRead) -> Result<Vec<Record>, DumpError> {
+    let mut records = Vec::new();
+
+    for (n, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| DumpError::Validation(e.to_string()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line)
+            .map_err(|e| DumpError::Validation(format!("line {}: {}", n + 1, e)))?;
+        records.push(record);
+    }
+
+    match records.first() {
+        Some(Record::Archive { version }) if *version <= ARCHIVE_VERSION => Ok(records),
+        Some(Record::Archive { version }) => Err(DumpError::Validation(format!(
+            "archive version {} is newer than this server ({})",
+            version, ARCHIVE_VERSION
+        ))),
+        _ => Err(DumpError::Validation(
+            "archive should start with an archive record".to_string(),
+        )),
+    }
+}
+
+/// `value` under `key`, and `member` in `set` to find it.
+fn stored(key: String, set: &str, member: String, value: &Value) -> Vec<StoreWrite> {
+    vec![
+        StoreWrite::Set {
+            key,
+            value: value.to_string().into_bytes(),
+        },
+        StoreWrite::SetAdd {
+            set: set.to_string(),
+            member,
+        },
+    ]
+}
+
+/// Rebuilds the keyspace from `records`. Only works on an empty store, we
+/// don't try to merge an archive into existing data. The whole archive is
+/// checked first and then written in one transaction, so a failed import
+/// leaves the store as empty as it was. An `imported` key records when it was.
+pub async fn import(records: Vec<Record>, store: &dyn Store) -> Result<ImportSummary, DumpError> {
+    for set in &["headers", "topics", "versions", "forks", "results"] {
+        if !store.set_members(set).await?.is_empty() {
+            return Err(DumpError::Validation(format!(
+                "store is not empty ({} has members), refusing to import",
+                set
+            )));
+        }
+    }
+    if !store.hash_keys("reps").await?.is_empty() {
+        return Err(DumpError::Validation(
+            "store is not empty (reps has fields), refusing to import".to_string(),
+        ));
+    }
+
+    let mut summary = ImportSummary::default();
+    let mut writes = Vec::new();
+
+    for record in records {
+        match record {
+            Record::Archive { .. } => {}
+            Record::Header { id, value } => {
+                writes.extend(stored(format!("header:{}", id), "headers", id, &value));
+                summary.headers += 1;
+            }
+            Record::Topic { hash, value } => {
+                writes.extend(stored(format!("topic:{}", hash), "topics", hash, &value));
+                summary.topics += 1;
+            }
+            Record::Version { hash, value } => {
+                writes.extend(stored(
+                    format!("version:{}", hash),
+                    "versions",
+                    hash,
+                    &value,
+                ));
+                summary.versions += 1;
+            }
+            Record::History { id, entries } => {
+                let list = format!("history:{}", id);
+                writes.extend(entries.into_iter().map(|value| StoreWrite::ListPush {
+                    list: list.to_string(),
+                    value,
+                }));
+                summary.histories += 1;
+            }
+            Record::Fork { id, value } => {
+                let fork: Fork = serde_json::from_value(value.clone())
+                    .map_err(|e| DumpError::Validation(format!("fork {}: {}", id, e)))?;
+                writes.push(StoreWrite::SetAdd {
+                    set: format!("children:{}", fork.parent),
+                    member: id.to_string(),
+                });
+                writes.extend(stored(format!("fork:{}", id), "forks", id, &value));
+                summary.forks += 1;
+            }
+            Record::Result { hash, value } => {
+                writes.extend(stored(format!("result:{}", hash), "results", hash, &value));
+                summary.results += 1;
+            }
+            Record::Representative { id, value } => {
+                writes.push(StoreWrite::HashSet {
+                    hash: "reps".to_string(),
+                    field: id,
+                    value: value.to_string().into_bytes(),
+                });
+                summary.representatives += 1;
+            }
+        }
+    }
+
+    writes.push(StoreWrite::Set {
+        key: IMPORTED.to_string(),
+        value: Utc::now().to_rfc3339().into_bytes(),
+    });
+    if !store.transaction(IMPORTED, None, writes).await? {
+        return Err(DumpError::Validation(
+            "the store was imported into already, refusing to import".to_string(),
+        ));
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_store::MemoryStore;
+
+    fn archive(fork: &str) -> Vec<Record> {
+        read_ndjson(
+            format!(
+                "{{\"kind\":\"archive\",\"version\":1}}\n\
+                 {{\"kind\":\"header\",\"id\":\"a\",\"value\":{{}}}}\n\
+                 {{\"kind\":\"history\",\"id\":\"a\",\"entries\":[\"h\"]}}\n\
+                 {{\"kind\":\"representative\",\"id\":\"r\",\"value\":{{}}}}\n\
+                 {{\"kind\":\"fork\",\"id\":\"b\",\"value\":{}}}\n",
+                fork
+            )
+            .as_bytes(),
+        )
+        .unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn a_failed_import_writes_nothing() {
+        let store = MemoryStore::default();
+
+        let broken = archive("{}");
+        assert!(matches!(
+            import(broken, &store).await,
+            Err(DumpError::Validation(_))
+        ));
+        assert!(store.set_members("headers").await.unwrap().is_empty());
+        assert!(store.hash_keys("reps").await.unwrap().is_empty());
+
+        let parent = "00000000-0000-0000-0000-00000000000a";
+        let fork = format!(
+            r#"{{"id":"00000000-0000-0000-0000-00000000000b","parent":"{}","parent_hash":"h","at":"2021-01-01T00:00:00Z"}}"#,
+            parent
+        );
+        let summary = import(archive(&fork), &store).await.unwrap();
+        assert_eq!((summary.headers, summary.forks), (1, 1));
+        assert_eq!(
+            store.list_range("history:a", 0, -1).await.unwrap(),
+            vec!["h"]
+        );
+        assert_eq!(
+            store
+                .set_members(&format!("children:{}", parent))
+                .await
+                .unwrap(),
+            vec!["b"]
+        );
+        assert!(import(archive(&fork), &store).await.is_err());
+    }
+}
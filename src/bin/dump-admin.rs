@@ -0,0 +1,187 @@
+//! Operates a dump instance straight through its store, without going through HTTP.
+//!
+//! Uses the same `STORE_BACKEND`, `REDIS_*` and `STORE_PATH` variables as the server.
+
+use actix::clock::delay_for;
+use dotenv;
+use dump::{
+    archive, chain,
+    error::DumpError,
+    fsck,
+    model::{Topic, TopicCalculationResult, TopicHeader},
+    redis_util,
+    store::{self, Store},
+};
+use std::{
+    env,
+    fs::File,
+    io::{self, BufReader},
+    process,
+    time::Duration,
+};
+
+const USAGE: &str = "usage: dump-admin <command>
+
+commands:
+    list                    topics with their current hash
+    history <id>            every change to a topic (hash, time, operation, voter), newest first
+    versions <id>           every version of a topic, with its title
+    topic <hash>            one version of a topic
+    chain <id>              validate the version chain of a topic, fails if broken
+    results                 hashes of every stored result
+    result <hash>           the result for a topic hash
+    reps                    ids of every representative
+    rep <id>                one representative
+    export [file]           dump everything as ndjson, to stdout by default
+    import <file>           load an ndjson dump into an empty store, - for stdin
+    fsck                    check topic hashes, histories and headers, fails on problems";
+
+fn print_json(value: &impl serde::Serialize) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).expect("should be serializable")
+    );
+}
+
+/// The redis actor connects in the background, give it a moment before the first command.
+async fn wait_for(store: &dyn Store) -> Result<(), DumpError> {
+    let mut attempt = 0;
+    loop {
+        match store.ping().await {
+            Ok(()) => return Ok(()),
+            Err(_) if attempt < 20 => {
+                attempt += 1;
+                delay_for(Duration::from_millis(250)).await;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+async fn run(args: &[String], store: &dyn Store) -> Result<(), DumpError> {
+    let arg = |n: usize| {
+        args.get(n)
+            .map(|a| a.as_str())
+            .ok_or_else(|| DumpError::Validation(format!("missing argument\n\n{}", USAGE)))
+    };
+
+    match arg(0)? {
+        "list" => {
+            for id in redis_util::get_list("header", store).await? {
+                let header: TopicHeader = redis_util::get_object(&id, "header", store).await?;
+                println!("{}\t{}\t{}", header.id, header.hash, header.title);
+            }
+        }
+        "history" => {
+            for entry in redis_util::get_history(arg(1)?, store).await? {
+                let at = entry.at.map(|at| at.to_rfc3339()).unwrap_or_default();
+                let operation = entry
+                    .operation
+                    .map(|o| serde_json::to_value(o).expect("should be serializable"))
+                    .and_then(|o| o.as_str().map(|o| o.to_string()))
+                    .unwrap_or_default();
+                let actor = entry.actor.map(|a| a.to_string()).unwrap_or_default();
+                println!("{}\t{}\t{}\t{}", entry.hash, at, operation, actor);
+            }
+        }
+        "versions" => {
+            for entry in redis_util::get_history(arg(1)?, store).await? {
+                let hash = entry.hash;
+                match redis_util::get_object::<Topic>(&hash, "topic", store).await {
+                    Ok(topic) => println!("{}\t{}", hash, topic.title),
+                    Err(DumpError::NotFound(_)) => println!("{}\t(missing)", hash),
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        "chain" => {
+            let report = chain::validate(arg(1)?, store).await?;
+            print_json(&report);
+            if !report.is_valid() {
+                return Err(DumpError::Internal(format!(
+                    "{} problem(s) in the chain",
+                    report.problems.len()
+                )));
+            }
+        }
+        "topic" => {
+            let topic: Topic = redis_util::get_object(arg(1)?, "topic", store).await?;
+            print_json(&topic);
+        }
+        "results" => {
+            for hash in redis_util::get_list("result", store).await? {
+                println!("{}", hash);
+            }
+        }
+        "result" => {
+            let result: TopicCalculationResult =
+                redis_util::get_object(arg(1)?, "result", store).await?;
+            print_json(&result);
+        }
+        "reps" => {
+            for id in redis_util::get_representatives(store).await? {
+                println!("{}", id);
+            }
+        }
+        "rep" => {
+            print_json(&redis_util::get_representative(arg(1)?, store).await?);
+        }
+        "export" => {
+            let records = archive::export(store).await?;
+            let written = match args.get(1) {
+                Some(path) => File::create(path).and_then(|f| archive::write_ndjson(&records, f)),
+                None => archive::write_ndjson(&records, io::stdout().lock()),
+            };
+            written.map_err(|e| DumpError::Internal(e.to_string()))?;
+        }
+        "import" => {
+            let records = match arg(1)? {
+                "-" => archive::read_ndjson(io::stdin().lock())?,
+                path => {
+                    let file =
+                        File::open(path).map_err(|e| DumpError::Validation(e.to_string()))?;
+                    archive::read_ndjson(BufReader::new(file))?
+                }
+            };
+            print_json(&archive::import(records, store).await?);
+        }
+        "fsck" => {
+            let report = fsck::check(store).await?;
+            print_json(&report);
+            if !report.is_clean() {
+                return Err(DumpError::Internal(format!(
+                    "{} problem(s) found",
+                    report.problems.len()
+                )));
+            }
+        }
+        "help" | "--help" | "-h" => println!("{}", USAGE),
+        other => {
+            return Err(DumpError::Validation(format!(
+                "unknown command {}\n\n{}",
+                other, USAGE
+            )))
+        }
+    }
+
+    Ok(())
+}
+
+#[actix_web::main]
+async fn main() {
+    dotenv::dotenv().ok();
+    env_logger::init();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let store = store::from_env(false);
+
+    let result = match wait_for(store.as_ref()).await {
+        Ok(()) => run(&args, store.as_ref()).await,
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
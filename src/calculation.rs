@@ -0,0 +1,185 @@
+//! Liquid democracy over a topic, done on the server so a closing topic can
+//! get its result without a client around.
+//!
+//! Every voter holds one vote. Their vote map says how it is split between
+//! policies and other delegates. Weights are normalized per voter unless the
+//! topic's `is_normalize` is off, and count as their square root when
+//! `is_quadratic` is on (before normalizing). Whatever goes to a delegate is
+//! split again following that delegate's own map, until it lands on policies.
+//! Delegations are followed path by path: weight that would come back to a
+//! voter already on its path is going around in a cycle and is lost, so is
+//! weight going to a delegate with no vote.
+//!
+//! The output has the shape clients post to `/result/{hash}/`: policy scores
+//! and delegate influence (their own vote plus everything passed through
+//! them, each share counted once). Both are keyed by uuid, names can repeat.
+
+use crate::model::{Params, Topic};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+/// shares smaller than this aren't followed any further
+const EPSILON: f64 = 1e-12;
+/// delegations followed for one topic at most, the rest of the weight is lost
+const MAX_STEPS: usize = 1_000_000;
+
+struct Flow<'a> {
+    topic: &'a Topic,
+    params: Params,
+    scores: BTreeMap<Uuid, f64>,
+    influence: BTreeMap<Uuid, f64>,
+    steps: usize,
+}
+
+impl<'a> Flow<'a> {
+    fn weight_of(&self, v: f64) -> f64 {
+        if self.params.quadratic() {
+            v.sqrt()
+        } else {
+            v
+        }
+    }
+
+    /// `weight` arrives at `voter`, by way of the voters in `path`.
+    fn pass(&mut self, voter: Uuid, weight: f64, path: &mut Vec<Uuid>) {
+        let topic = self.topic;
+        *self.influence.entry(voter).or_insert(0.0) += weight;
+
+        let votes = match topic.votes.get(&voter) {
+            Some(votes) => votes,
+            None => return,
+        };
+        let total: f64 = if self.params.normalize() {
+            votes
+                .values()
+                .filter(|v| **v > 0.0)
+                .map(|v| self.weight_of(*v))
+                .sum()
+        } else {
+            1.0
+        };
+        if total <= 0.0 {
+            return;
+        }
+
+        path.push(voter);
+        for (target, v) in votes.iter().filter(|(_, v)| **v > 0.0) {
+            let share = weight * self.weight_of(*v) / total;
+            if let Some(score) = self.scores.get_mut(target) {
+                *score += share;
+                continue;
+            }
+
+            let delegate = topic.votes.contains_key(target) || topic.delegates.contains_key(target);
+            if !delegate || path.contains(target) || share < EPSILON {
+                continue;
+            }
+            if self.steps >= MAX_STEPS {
+                log::warn!("topic {} has too many delegations to follow", topic.id);
+                continue;
+            }
+            self.steps += 1;
+            self.pass(*target, share, path);
+        }
+        path.pop();
+    }
+}
+
+pub fn liquid(topic: &Topic) -> Value {
+    let mut flow = Flow {
+        topic,
+        params: topic.aggregation.unwrap_or_default().params,
+        scores: topic.policies.keys().map(|p| (*p, 0.0)).collect(),
+        influence: BTreeMap::new(),
+        steps: 0,
+    };
+
+    // every voter starts out holding their own vote
+    for voter in topic.votes.keys() {
+        flow.pass(*voter, 1.0, &mut Vec::new());
+    }
+
+    let policies: BTreeMap<String, f64> = flow
+        .scores
+        .iter()
+        .map(|(id, score)| (id.to_string(), *score))
+        .collect();
+    let delegates: BTreeMap<String, f64> = flow
+        .influence
+        .iter()
+        .map(|(id, w)| (id.to_string(), *w))
+        .collect();
+
+    json!([policies, delegates])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vote(targets: &[(Uuid, f64)]) -> BTreeMap<Uuid, f64> {
+        targets.iter().cloned().collect()
+    }
+
+    #[test]
+    fn cycles_are_counted_once() {
+        let mut topic = Topic::new("t", "d");
+        let bread = topic.add_policy("bread");
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        topic.set_delegate(&a, "a");
+        topic.set_delegate(&b, "b");
+        topic.set_delegate(&c, "c");
+        topic.set_vote(&a, vote(&[(b, 1.0)]));
+        topic.set_vote(&b, vote(&[(a, 1.0)]));
+        topic.set_vote(&c, vote(&[(bread, 1.0)]));
+
+        assert_eq!(
+            liquid(&topic),
+            json!([
+                {bread.to_string(): 1.0},
+                {a.to_string(): 2.0, b.to_string(): 2.0, c.to_string(): 1.0}
+            ])
+        );
+    }
+
+    #[test]
+    fn delegation_follows_through() {
+        let mut topic = Topic::new("t", "d");
+        let bread = topic.add_policy("bread");
+        let rice = topic.add_policy("rice");
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        topic.set_delegate(&a, "a");
+        topic.set_delegate(&b, "b");
+        topic.set_vote(&a, vote(&[(bread, 3.0), (rice, 1.0)]));
+        topic.set_vote(&b, vote(&[(a, 1.0), (rice, 1.0)]));
+
+        assert_eq!(
+            liquid(&topic),
+            json!([
+                {bread.to_string(): 1.125, rice.to_string(): 0.875},
+                {a.to_string(): 1.5, b.to_string(): 1.0}
+            ])
+        );
+    }
+
+    #[test]
+    fn same_names_stay_apart() {
+        let mut topic = Topic::new("t", "d");
+        let first = topic.add_policy("bread");
+        let second = topic.add_policy("bread");
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        topic.set_delegate(&a, "a");
+        topic.set_delegate(&b, "a");
+        topic.set_vote(&a, vote(&[(first, 1.0)]));
+        topic.set_vote(&b, vote(&[(second, 1.0)]));
+
+        assert_eq!(
+            liquid(&topic),
+            json!([
+                {first.to_string(): 1.0, second.to_string(): 1.0},
+                {a.to_string(): 1.0, b.to_string(): 1.0}
+            ])
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! Walks a topic's version chain from its header back to the first version.
+//!
+//! Every `version:{hash}` names the topic it stored and its parent version, so
+//! the chain can be checked link by link: each version and topic has to hash to
+//! its key, and the topics met along the way have to be exactly what
+//! `history:{id}` lists, in the same order. A version inserted into or dropped
+//! from either one shows up as a problem.
+
+use crate::{
+    error::DumpError,
+    hashing,
+    model::{Topic, TopicHeader, TopicVersion},
+    redis_util,
+    store::Store,
+    RedisObject,
+};
+use serde::Serialize;
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "problem", rename_all = "snake_case")]
+pub enum Problem {
+    /// nothing stored under `version:{version}`
+    MissingVersion { version: String },
+    /// the version under `version:{version}` hashes to `computed`
+    VersionMismatch { version: String, computed: String },
+    /// the version's topic isn't stored
+    MissingTopic { version: String, topic: String },
+    /// the version's topic doesn't hash to its key
+    TopicMismatch { version: String, topic: String },
+    /// the header's hash is not the topic of the newest version
+    HeaderMismatch { header: String, chain: String },
+    /// `position` versions back, the history says `history` but the chain says `chain`
+    HistoryMismatch {
+        position: usize,
+        history: Option<String>,
+        chain: String,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub id: String,
+    /// versions in the chain, newest first
+    pub versions: Vec<String>,
+    /// history entries older than the chain, written before versions existed
+    pub unchained: Vec<String>,
+    pub problems: Vec<Problem>,
+}
+
+impl Report {
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+pub async fn validate(id: &str, store: &dyn Store) -> Result<Report, DumpError> {
+    let header: TopicHeader = redis_util::get_object(id, "header", store).await?;
+    let history: Vec<String> = redis_util::get_history(id, store)
+        .await?
+        .into_iter()
+        .map(|e| e.hash)
+        .collect();
+
+    let mut report = Report {
+        id: id.to_string(),
+        versions: Vec::new(),
+        unchained: Vec::new(),
+        problems: Vec::new(),
+    };
+
+    let mut next = header.version.clone();
+    let mut first = true;
+
+    while let Some(hash) = next.take() {
+        // a chain can't be longer than the history, anything past it is a loop or garbage
+        if report.versions.len() > history.len() {
+            report.problems.push(Problem::HistoryMismatch {
+                position: report.versions.len(),
+                history: None,
+                chain: hash,
+            });
+            break;
+        }
+
+        let version: TopicVersion = match redis_util::get_object(&hash, "version", store).await {
+            Ok(version) => version,
+            Err(DumpError::NotFound(_)) => {
+                report
+                    .problems
+                    .push(Problem::MissingVersion { version: hash });
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+
+        let computed = version.hash();
+        if computed != hash {
+            report.problems.push(Problem::VersionMismatch {
+                version: hash.to_string(),
+                computed,
+            });
+        }
+
+        match redis_util::get_object::<Topic>(&version.topic, "topic", store).await {
+            Ok(topic) => {
+                if !hashing::verify(&topic, &version.topic) {
+                    report.problems.push(Problem::TopicMismatch {
+                        version: hash.to_string(),
+                        topic: version.topic.to_string(),
+                    });
+                }
+            }
+            Err(DumpError::NotFound(_)) => report.problems.push(Problem::MissingTopic {
+                version: hash.to_string(),
+                topic: version.topic.to_string(),
+            }),
+            Err(e) => return Err(e),
+        }
+
+        if first && version.topic != header.hash {
+            report.problems.push(Problem::HeaderMismatch {
+                header: header.hash.to_string(),
+                chain: version.topic.to_string(),
+            });
+        }
+        first = false;
+
+        let position = report.versions.len();
+        if history.get(position) != Some(&version.topic) {
+            report.problems.push(Problem::HistoryMismatch {
+                position,
+                history: history.get(position).cloned(),
+                chain: version.topic.to_string(),
+            });
+        }
+
+        report.versions.push(hash);
+        next = version.parent;
+    }
+
+    report.unchained = history
+        .iter()
+        .skip(report.versions.len())
+        .cloned()
+        .collect();
+
+    Ok(report)
+}
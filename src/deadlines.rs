@@ -0,0 +1,99 @@
+//! Opens and closes topics when their scheduled time comes.
+//!
+//! The `Deadlines` actor sweeps every header each `DEADLINE_INTERVAL` seconds
+//! (10 by default). Drafts past `opens_at` are opened, drafts past `closes_at`
+//! without an `opens_at` are archived, open topics past `closes_at` are closed, which pins their final version and stores its
+//! result. Edits past `closes_at` are refused by the commit path already, the
+//! sweep only makes it official.
+//!
+//! Several servers on one store can all run it: state changes watch the
+//! header, so only one of them gets to close a topic.
+
+use crate::{
+    error::DumpError,
+    model::{State, TopicHeader},
+    redis_util,
+    store::Store,
+};
+use actix::prelude::*;
+use chrono::Utc;
+use std::{cell::Cell, env, rc::Rc, sync::Arc, time::Duration};
+
+pub struct Deadlines {
+    store: Arc<dyn Store>,
+    interval: Duration,
+    /// a sweep is still running, skip this tick
+    busy: Rc<Cell<bool>>,
+}
+
+impl Deadlines {
+    pub fn new(store: Arc<dyn Store>) -> Self {
+        let seconds = env::var("DEADLINE_INTERVAL")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(10);
+
+        Self {
+            store,
+            interval: Duration::from_secs(seconds),
+            busy: Rc::new(Cell::new(false)),
+        }
+    }
+}
+
+impl Actor for Deadlines {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        log::info!("checking deadlines every {:?}", self.interval);
+
+        ctx.run_interval(self.interval, |act, _ctx| {
+            if act.busy.get() {
+                return;
+            }
+            act.busy.set(true);
+
+            let (store, busy) = (act.store.clone(), act.busy.clone());
+            actix::spawn(async move {
+                if let Err(e) = sweep(store.as_ref()).await {
+                    log::warn!("deadline sweep failed: {}", e);
+                }
+                busy.set(false);
+            });
+        });
+    }
+}
+
+/// Opens and closes whatever is due. Returns the ids of the topics it moved.
+pub async fn sweep(store: &dyn Store) -> Result<Vec<String>, DumpError> {
+    let now = Utc::now();
+    let mut moved = Vec::new();
+
+    for id in redis_util::get_list("header", store).await? {
+        let base = match redis_util::get_slice(&id, "header", store).await? {
+            Some(base) => base,
+            None => continue,
+        };
+        let header: TopicHeader = serde_json::from_slice(&base)?;
+
+        let next = match header.state {
+            State::Draft if header.opens_at.map_or(false, |at| at <= now) => State::Open,
+            // never opened, there is nothing to close
+            State::Draft if header.closes_at.map_or(false, |at| at <= now) => State::Archived,
+            State::Open if header.closes_at.map_or(false, |at| at <= now) => State::Closed,
+            _ => continue,
+        };
+
+        match redis_util::set_state(&base, next, store).await {
+            Ok(_) => {
+                log::info!("topic {} is {:?} now", id, next);
+                moved.push(id);
+            }
+            // someone else got there first, or it changed under us; next sweep will tell
+            Err(DumpError::Conflict { .. }) => {}
+            Err(e) => log::warn!("could not move topic {} to {:?}: {}", id, next, e),
+        }
+    }
+
+    Ok(moved)
+}
@@ -0,0 +1,190 @@
+//! What changed between two versions of a topic.
+
+use crate::model::{Aggregation, Topic};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Edit<T> {
+    pub from: T,
+    pub to: T,
+}
+
+/// Changes to a `uuid -> text` map, policies or delegates.
+#[derive(Debug, Serialize, Default)]
+pub struct MapDiff {
+    pub added: BTreeMap<Uuid, String>,
+    pub removed: BTreeMap<Uuid, String>,
+    pub renamed: BTreeMap<Uuid, Edit<String>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TopicDiff {
+    pub title: Option<Edit<String>>,
+    pub description: Option<Edit<String>>,
+    pub policies: MapDiff,
+    pub delegates: MapDiff,
+    /// display order of the policies, when it changed
+    pub policy_order: Option<Edit<Vec<Uuid>>>,
+    /// aggregation settings, when they changed. `None` is the default
+    pub aggregation: Option<Edit<Option<Aggregation>>>,
+    /// voter -> policy -> weight, only the weights that changed. `None` is no
+    /// weight at all, either side of the vote map didn't have the policy.
+    pub votes: BTreeMap<Uuid, BTreeMap<Uuid, Edit<Option<f64>>>>,
+}
+
+fn edit(from: &str, to: &str) -> Option<Edit<String>> {
+    if from == to {
+        return None;
+    }
+    Some(Edit {
+        from: from.to_string(),
+        to: to.to_string(),
+    })
+}
+
+fn map_diff(from: &BTreeMap<Uuid, String>, to: &BTreeMap<Uuid, String>) -> MapDiff {
+    let mut diff = MapDiff::default();
+
+    for (id, old) in from {
+        match to.get(id) {
+            Some(new) => {
+                if let Some(e) = edit(old, new) {
+                    diff.renamed.insert(*id, e);
+                }
+            }
+            None => {
+                diff.removed.insert(*id, old.to_string());
+            }
+        }
+    }
+
+    for (id, new) in to {
+        if !from.contains_key(id) {
+            diff.added.insert(*id, new.to_string());
+        }
+    }
+
+    diff
+}
+
+pub fn diff(from: &Topic, to: &Topic) -> TopicDiff {
+    let empty = BTreeMap::new();
+    let mut votes = BTreeMap::new();
+
+    let voters = from.votes.keys().chain(to.votes.keys());
+    for voter in voters {
+        if votes.contains_key(voter) {
+            continue;
+        }
+        let old = from.votes.get(voter).unwrap_or(&empty);
+        let new = to.votes.get(voter).unwrap_or(&empty);
+
+        let mut changed = BTreeMap::new();
+        for policy in old.keys().chain(new.keys()) {
+            let (old, new) = (old.get(policy).copied(), new.get(policy).copied());
+            if old != new {
+                changed.insert(*policy, Edit { from: old, to: new });
+            }
+        }
+
+        if !changed.is_empty() {
+            votes.insert(*voter, changed);
+        }
+    }
+
+    let (old, new) = (from.ordered_policies(), to.ordered_policies());
+    let policy_order = if old != new {
+        Some(Edit { from: old, to: new })
+    } else {
+        None
+    };
+
+    let aggregation = if from.aggregation != to.aggregation {
+        Some(Edit {
+            from: from.aggregation,
+            to: to.aggregation,
+        })
+    } else {
+        None
+    };
+
+    TopicDiff {
+        title: edit(&from.title, &to.title),
+        description: edit(&from.description, &to.description),
+        policies: map_diff(&from.policies, &to.policies),
+        delegates: map_diff(&from.delegates, &to.delegates),
+        policy_order,
+        aggregation,
+        votes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_topic_has_no_changes() {
+        let mut topic = Topic::new("t", "d");
+        topic.add_policy("bread");
+        let d = diff(&topic, &topic.clone());
+
+        assert!(d.title.is_none() && d.description.is_none());
+        assert!(d.policies.added.is_empty() && d.policies.removed.is_empty());
+        assert!(d.policy_order.is_none() && d.aggregation.is_none());
+        assert!(d.votes.is_empty());
+    }
+
+    #[test]
+    fn every_kind_of_change_shows() {
+        let mut from = Topic::new("t", "d");
+        let bread = from.add_policy("bread");
+        let rice = from.add_policy("rice");
+        let voter = Uuid::new_v4();
+        from.set_delegate(&voter, "v");
+        from.set_vote(&voter, vec![(bread, 1.0)].into_iter().collect());
+
+        let mut to = from.clone();
+        to.title = "new".to_string();
+        to.rename_policy(&bread, "toast");
+        to.remove_policy(&rice, false);
+        let noodles = to.add_policy("noodles");
+        to.set_vote(&voter, vec![(noodles, 1.0)].into_iter().collect());
+
+        let d = diff(&from, &to);
+        assert_eq!(d.title.unwrap().to, "new");
+        assert_eq!(d.policies.renamed[&bread].to, "toast");
+        assert_eq!(d.policies.removed[&rice], "rice");
+        assert_eq!(d.policies.added[&noodles], "noodles");
+        assert!(d.delegates.renamed.is_empty());
+
+        let votes = &d.votes[&voter];
+        assert_eq!(
+            votes[&bread],
+            Edit {
+                from: Some(1.0),
+                to: None
+            }
+        );
+        assert_eq!(
+            votes[&noodles],
+            Edit {
+                from: None,
+                to: Some(1.0)
+            }
+        );
+    }
+
+    #[test]
+    fn reordering_is_a_change() {
+        let mut from = Topic::new("t", "d");
+        from.add_policy("bread");
+        from.add_policy("rice");
+        let mut to = from.clone();
+        to.policy_order = from.ordered_policies().into_iter().rev().collect();
+
+        assert!(diff(&from, &to).policy_order.is_some());
+    }
+}
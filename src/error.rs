@@ -0,0 +1,121 @@
+use crate::store::StoreError;
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde::Serialize;
+use serde_json::json;
+use std::fmt;
+
+/// What is wrong with one field of a request, `field` is a dotted path into the body.
+#[derive(Debug, Serialize, Clone)]
+pub struct FieldError {
+    pub field: String,
+    pub mes: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, mes: &str) -> Self {
+        Self {
+            field: field.to_string(),
+            mes: mes.to_string(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DumpError {
+    /// nothing stored under that id or hash
+    NotFound(String),
+    /// the store could not be reached
+    Unavailable(String),
+    /// something stored could not be read back, request bodies are `Validation`
+    Deserialize(String),
+    /// the request itself is invalid
+    Validation(String),
+    /// the request is invalid, field by field
+    Fields(Vec<FieldError>),
+    /// the topic moved on since the client read it, `hash` is where it is now
+    Conflict { hash: String },
+    /// the topic's lifecycle state doesn't allow it
+    State(String),
+    /// anything else that went wrong on our side
+    Internal(String),
+}
+
+impl DumpError {
+    fn kind(&self) -> &'static str {
+        match self {
+            DumpError::NotFound(_) => "not_found",
+            DumpError::Unavailable(_) => "unavailable",
+            DumpError::Deserialize(_) => "deserialize",
+            DumpError::Validation(_) | DumpError::Fields(_) => "validation",
+            DumpError::Conflict { .. } => "conflict",
+            DumpError::State(_) => "state",
+            DumpError::Internal(_) => "internal",
+        }
+    }
+}
+
+impl fmt::Display for DumpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DumpError::NotFound(mes) => write!(f, "could not find {}", mes),
+            DumpError::Unavailable(mes) => write!(f, "store unavailable: {}", mes),
+            DumpError::Deserialize(mes) => write!(f, "could not read data: {}", mes),
+            DumpError::Validation(mes) => write!(f, "invalid request: {}", mes),
+            DumpError::Fields(fields) => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|e| format!("{}: {}", e.field, e.mes))
+                    .collect();
+                write!(f, "invalid request: {}", fields.join(", "))
+            }
+            DumpError::Conflict { .. } => {
+                write!(f, "topic was updated by someone else, rebase your edit")
+            }
+            DumpError::State(mes) => write!(f, "not allowed in this state: {}", mes),
+            DumpError::Internal(mes) => write!(f, "internal error: {}", mes),
+        }
+    }
+}
+
+impl std::error::Error for DumpError {}
+
+impl From<StoreError> for DumpError {
+    fn from(e: StoreError) -> Self {
+        match e {
+            StoreError::Unavailable(mes) => DumpError::Unavailable(mes),
+            StoreError::Backend(mes) => DumpError::Internal(mes),
+        }
+    }
+}
+
+impl From<serde_json::Error> for DumpError {
+    fn from(e: serde_json::Error) -> Self {
+        DumpError::Deserialize(e.to_string())
+    }
+}
+
+impl ResponseError for DumpError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DumpError::NotFound(_) => StatusCode::NOT_FOUND,
+            DumpError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            DumpError::Deserialize(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            DumpError::Validation(_) | DumpError::Fields(_) => StatusCode::BAD_REQUEST,
+            DumpError::Conflict { .. } => StatusCode::CONFLICT,
+            DumpError::State(_) => StatusCode::CONFLICT,
+            DumpError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut body = json!({"status":"error", "error": self.kind(), "mes": self.to_string()});
+
+        match self {
+            DumpError::Conflict { hash } => body["hash"] = json!(hash),
+            DumpError::Fields(fields) => body["fields"] = json!(fields),
+            _ => {}
+        }
+
+        HttpResponse::build(self.status_code()).json(body)
+    }
+}
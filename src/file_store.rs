@@ -0,0 +1,515 @@
+use crate::{
+    memory_store::Keyspace,
+    store::{Store, StoreError, Write as StoreWrite},
+};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+
+/// One line of the log. Replaying every entry in order rebuilds the keyspace.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Entry {
+    Set {
+        key: String,
+        value: String,
+    },
+    SetAdd {
+        set: String,
+        member: String,
+    },
+    ListPush {
+        list: String,
+        value: String,
+    },
+    HashSet {
+        hash: String,
+        field: String,
+        value: String,
+    },
+    /// the writes of one transaction, kept on a single line so they land together
+    Batch {
+        writes: Vec<Entry>,
+    },
+}
+
+impl Entry {
+    fn apply(self, keyspace: &mut Keyspace) -> i64 {
+        match self {
+            Entry::Set { key, value } => {
+                keyspace.set(&key, value.as_bytes());
+                0
+            }
+            Entry::SetAdd { set, member } => keyspace.set_add(&set, &member),
+            Entry::ListPush { list, value } => keyspace.list_push(&list, &value),
+            Entry::HashSet { hash, field, value } => {
+                keyspace.hash_set(&hash, &field, value.as_bytes())
+            }
+            Entry::Batch { writes } => {
+                writes.into_iter().for_each(|w| {
+                    w.apply(keyspace);
+                });
+                0
+            }
+        }
+    }
+
+    fn from_write(write: StoreWrite) -> Result<Self, StoreError> {
+        Ok(match write {
+            StoreWrite::Set { key, value } => Entry::Set {
+                key,
+                value: text(&value)?,
+            },
+            StoreWrite::SetAdd { set, member } => Entry::SetAdd { set, member },
+            StoreWrite::ListPush { list, value } => Entry::ListPush { list, value },
+            StoreWrite::HashSet { hash, field, value } => Entry::HashSet {
+                hash,
+                field,
+                value: text(&value)?,
+            },
+        })
+    }
+}
+
+fn text(value: &[u8]) -> Result<String, StoreError> {
+    String::from_utf8(value.to_vec())
+        .map_err(|_| StoreError::Backend("the file store only keeps utf-8 values".to_string()))
+}
+
+struct Log {
+    keyspace: Keyspace,
+    path: PathBuf,
+    file: File,
+    /// an append failed and its partial line couldn't be cut off, writing
+    /// after it would break the log for the next `open`
+    broken: bool,
+}
+
+/// Embedded store for small deployments: the whole keyspace is kept in memory
+/// and every write is appended to a newline delimited JSON log on disk.
+///
+/// Only one process can have the log open, see `open`. Every write is synced
+/// to disk before it returns, on the calling thread and with the store locked,
+/// so a slow disk stalls the server's worker for that long.
+pub struct FileStore {
+    log: Mutex<Log>,
+    /// holds the lock on `{path}.lock` for as long as the store is open
+    _lock: File,
+}
+
+impl FileStore {
+    /// Replays the log at `path` (if there is one). Fails right away if some
+    /// other process, a running server say, has it open.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let lock = lock(path)?;
+        let mut keyspace = Keyspace::default();
+        // where the entries that replayed fine end
+        let mut good = 0;
+
+        if path.exists() {
+            let mut reader = BufReader::new(File::open(path)?);
+            let mut line = String::new();
+            let mut pending: Option<String> = None;
+            let mut n = 0;
+
+            while reader.read_line(&mut line)? > 0 {
+                if !line.trim().is_empty() {
+                    n += 1;
+                    if let Some(e) = pending.take() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("entry {} of {:?} is broken: {}", n - 1, path, e),
+                        ));
+                    }
+                    match serde_json::from_str::<Entry>(&line) {
+                        // only whole lines were acknowledged
+                        Ok(_) if !line.ends_with('\n') => {
+                            pending = Some("no newline at its end".to_string())
+                        }
+                        Ok(entry) => {
+                            entry.apply(&mut keyspace);
+                        }
+                        Err(e) => pending = Some(e.to_string()),
+                    }
+                }
+                if pending.is_none() {
+                    good += line.len() as u64;
+                }
+                line.clear();
+            }
+
+            // a write that was cut short, everything before it is fine
+            if let Some(e) = pending {
+                log::warn!("cutting the last entry off {:?}: {}", path, e);
+            }
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() > good {
+            file.set_len(good)?;
+        }
+
+        Ok(Self {
+            log: Mutex::new(Log {
+                keyspace,
+                path: path.to_path_buf(),
+                file,
+                broken: false,
+            }),
+            _lock: lock,
+        })
+    }
+
+    /// Rewrites the log so it holds just what the keyspace needs, it would grow
+    /// forever with overwritten headers otherwise.
+    pub fn compact(&self) -> io::Result<()> {
+        let mut log = self
+            .log
+            .lock()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+
+        compact(&log.path, &log.keyspace)?;
+        log.file = OpenOptions::new().append(true).open(&log.path)?;
+        log.broken = false;
+        Ok(())
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Log>, StoreError> {
+        self.log
+            .lock()
+            .map_err(|e| StoreError::Backend(e.to_string()))
+    }
+
+    /// writes the entry to disk first, and only then to the keyspace
+    fn append(&self, entry: Entry) -> Result<i64, StoreError> {
+        let mut log = self.lock()?;
+        Self::append_locked(&mut log, entry)
+    }
+
+    fn append_locked(log: &mut Log, entry: Entry) -> Result<i64, StoreError> {
+        let mut line = serde_json::to_vec(&entry).expect("log entries should be serializable");
+        line.push(b'\n');
+
+        if log.broken {
+            return Err(StoreError::Unavailable(
+                "the log has a broken entry at its end, restart to recover".to_string(),
+            ));
+        }
+
+        let len = log
+            .file
+            .metadata()
+            .map_err(|e| StoreError::Unavailable(e.to_string()))?
+            .len();
+        if let Err(e) = log.file.write_all(&line).and_then(|_| log.file.sync_data()) {
+            // cut off whatever part of the line made it, so the next one starts clean
+            if let Err(truncate) = log.file.set_len(len) {
+                log::error!("could not cut a failed write off the log: {}", truncate);
+                log.broken = true;
+            }
+            return Err(StoreError::Unavailable(e.to_string()));
+        }
+
+        Ok(entry.apply(&mut log.keyspace))
+    }
+}
+
+/// Takes an exclusive lock on `{path}.lock`, released when the returned file is
+/// closed, also when the process dies.
+#[cfg(unix)]
+fn lock(path: &Path) -> io::Result<File> {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(&lock_path)?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        let e = io::Error::last_os_error();
+        return Err(match e.kind() {
+            io::ErrorKind::WouldBlock => io::Error::new(
+                io::ErrorKind::WouldBlock,
+                format!("{:?} is open in another process", path),
+            ),
+            _ => e,
+        });
+    }
+    Ok(file)
+}
+
+#[cfg(not(unix))]
+fn lock(path: &Path) -> io::Result<File> {
+    log::warn!("can't lock {:?} here, don't open it twice", path);
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(lock_path)
+}
+
+/// Rewrites the log at `path` so it holds just enough entries to rebuild `keyspace`.
+fn compact(path: &Path, keyspace: &Keyspace) -> io::Result<()> {
+    let mut tmp = PathBuf::from(path);
+    tmp.set_extension("compact");
+
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    let mut write = |entry: Entry| -> io::Result<()> {
+        serde_json::to_writer(&mut writer, &entry)?;
+        writer.write_all(b"\n")
+    };
+
+    for (key, value) in &keyspace.strings {
+        let value = String::from_utf8_lossy(value).to_string();
+        write(Entry::Set {
+            key: key.to_string(),
+            value,
+        })?;
+    }
+    for (set, members) in &keyspace.sets {
+        for member in members {
+            write(Entry::SetAdd {
+                set: set.to_string(),
+                member: member.to_string(),
+            })?;
+        }
+    }
+    for (list, values) in &keyspace.lists {
+        for value in values {
+            write(Entry::ListPush {
+                list: list.to_string(),
+                value: value.to_string(),
+            })?;
+        }
+    }
+    for (hash, fields) in &keyspace.hashes {
+        for (field, value) in fields {
+            let value = String::from_utf8_lossy(value).to_string();
+            write(Entry::HashSet {
+                hash: hash.to_string(),
+                field: field.to_string(),
+                value,
+            })?;
+        }
+    }
+
+    writer.into_inner()?.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+#[async_trait(?Send)]
+impl Store for FileStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StoreError> {
+        Ok(self.lock()?.keyspace.get(key))
+    }
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<(), StoreError> {
+        self.append(Entry::Set {
+            key: key.to_string(),
+            value: text(value)?,
+        })
+        .map(|_| ())
+    }
+
+    async fn set_add(&self, set: &str, member: &str) -> Result<i64, StoreError> {
+        self.append(Entry::SetAdd {
+            set: set.to_string(),
+            member: member.to_string(),
+        })
+    }
+
+    async fn set_members(&self, set: &str) -> Result<Vec<String>, StoreError> {
+        Ok(self.lock()?.keyspace.set_members(set))
+    }
+
+    async fn list_push(&self, list: &str, value: &str) -> Result<i64, StoreError> {
+        self.append(Entry::ListPush {
+            list: list.to_string(),
+            value: value.to_string(),
+        })
+    }
+
+    async fn list_range(
+        &self,
+        list: &str,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<String>, StoreError> {
+        Ok(self.lock()?.keyspace.list_range(list, start, stop))
+    }
+
+    async fn list_len(&self, list: &str) -> Result<i64, StoreError> {
+        Ok(self.lock()?.keyspace.list_len(list))
+    }
+
+    async fn hash_set(&self, hash: &str, field: &str, value: &[u8]) -> Result<i64, StoreError> {
+        self.append(Entry::HashSet {
+            hash: hash.to_string(),
+            field: field.to_string(),
+            value: text(value)?,
+        })
+    }
+
+    async fn hash_get(&self, hash: &str, field: &str) -> Result<Option<Vec<u8>>, StoreError> {
+        Ok(self.lock()?.keyspace.hash_get(hash, field))
+    }
+
+    async fn hash_keys(&self, hash: &str) -> Result<Vec<String>, StoreError> {
+        Ok(self.lock()?.keyspace.hash_keys(hash))
+    }
+
+    async fn transaction(
+        &self,
+        watch: &str,
+        expected: Option<&[u8]>,
+        writes: Vec<StoreWrite>,
+    ) -> Result<bool, StoreError> {
+        let writes = writes
+            .into_iter()
+            .map(Entry::from_write)
+            .collect::<Result<Vec<Entry>, StoreError>>()?;
+
+        let mut log = self.lock()?;
+
+        if !log.keyspace.holds(watch, expected) {
+            return Ok(false);
+        }
+
+        Self::append_locked(&mut log, Entry::Batch { writes }).map(|_| true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use uuid::Uuid;
+
+    fn path() -> PathBuf {
+        env::temp_dir().join(format!("dump-test-{}.log", Uuid::new_v4()))
+    }
+
+    fn remove(path: &Path) {
+        fs::remove_file(path).unwrap();
+        let mut lock = path.as_os_str().to_owned();
+        lock.push(".lock");
+        fs::remove_file(lock).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn writes_survive_a_restart() {
+        let path = path();
+        {
+            let store = FileStore::open(&path).unwrap();
+            store.set("header:x", b"one").await.unwrap();
+            store.set("header:x", b"two").await.unwrap();
+            store.set_add("headers", "x").await.unwrap();
+            store.list_push("history:x", "a").await.unwrap();
+            store.list_push("history:x", "b").await.unwrap();
+            let writes = vec![StoreWrite::Set {
+                key: "topic:h".to_string(),
+                value: b"{}".to_vec(),
+            }];
+            assert!(store
+                .transaction("header:x", Some(b"two"), writes)
+                .await
+                .unwrap());
+        }
+
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(store.get("header:x").await.unwrap(), Some(b"two".to_vec()));
+        assert_eq!(store.set_members("headers").await.unwrap(), vec!["x"]);
+        assert_eq!(
+            store.list_range("history:x", 0, -1).await.unwrap(),
+            vec!["a", "b"]
+        );
+        assert_eq!(store.get("topic:h").await.unwrap(), Some(b"{}".to_vec()));
+
+        // compacted down to one entry per value
+        store.compact().unwrap();
+        let lines = fs::read_to_string(&path).unwrap().lines().count();
+        assert_eq!(lines, 5);
+
+        store.set("header:y", b"three").await.unwrap();
+        drop(store);
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(
+            store.get("header:y").await.unwrap(),
+            Some(b"three".to_vec())
+        );
+
+        drop(store);
+        remove(&path);
+    }
+
+    #[actix_rt::test]
+    async fn only_one_process_opens_the_log() {
+        let path = path();
+        let store = FileStore::open(&path).unwrap();
+
+        let err = FileStore::open(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+        store.set("header:b", b"one").await.unwrap();
+        drop(store);
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(store.get("header:b").await.unwrap(), Some(b"one".to_vec()));
+
+        drop(store);
+        remove(&path);
+    }
+
+    #[test]
+    fn a_cut_off_last_line_is_ignored() {
+        let path = path();
+        fs::write(
+            &path,
+            "{\"op\":\"set\",\"key\":\"a\",\"value\":\"1\"}\n{\"op\":\"set\",\"ke",
+        )
+        .unwrap();
+
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(store.lock().unwrap().keyspace.get("a"), Some(b"1".to_vec()));
+
+        // and the next write starts on a line of its own
+        store
+            .append(Entry::Set {
+                key: "b".to_string(),
+                value: "2".to_string(),
+            })
+            .unwrap();
+        drop(store);
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(store.lock().unwrap().keyspace.get("b"), Some(b"2".to_vec()));
+
+        drop(store);
+        remove(&path);
+    }
+
+    #[test]
+    fn a_broken_line_in_the_middle_is_refused() {
+        let path = path();
+        fs::write(
+            &path,
+            "{\"op\":\"set\",\"ke\n{\"op\":\"set\",\"key\":\"a\",\"value\":\"1\"}\n",
+        )
+        .unwrap();
+
+        let err = FileStore::open(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        remove(&path);
+    }
+}
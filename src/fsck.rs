@@ -0,0 +1,205 @@
+//! Integrity check over the stored topics.
+//!
+//! Nothing recomputes a topic hash on read, so this walks the `topics`,
+//! `versions` and `headers` sets and every `history:{id}` list and reports
+//! whatever doesn't add up. It only reads, fixing things is left to whoever runs it.
+
+use crate::{
+    error::DumpError,
+    hashing,
+    model::{HistoryEntry, Topic, TopicHeader, TopicVersion},
+    store::Store,
+    RedisObject,
+};
+use serde::Serialize;
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "problem", rename_all = "snake_case")]
+pub enum Problem {
+    /// the blob under `key` (a topic or a version) hashes to something else
+    HashMismatch { key: String, computed: String },
+    /// the blob under `key` is not valid json for its type
+    Unreadable { key: String, error: String },
+    /// `history:{id}` points at a topic that isn't stored
+    MissingTopic { id: String, hash: String },
+    /// `header:{id}` doesn't point at the last entry of its history
+    StaleHeader {
+        id: String,
+        header: String,
+        latest: String,
+    },
+    /// `set` lists `member` but there is nothing under its key
+    MissingKey { set: String, member: String },
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub topics: usize,
+    pub versions: usize,
+    pub headers: usize,
+    pub histories: usize,
+    pub problems: Vec<Problem>,
+    /// topics created before histories were kept and never edited since: a
+    /// header and its topic, but no history. Nothing wrong with those.
+    pub without_history: Vec<String>,
+}
+
+impl Report {
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+pub async fn check(store: &dyn Store) -> Result<Report, DumpError> {
+    let mut report = Report::default();
+
+    for hash in store.set_members("topics").await? {
+        report.topics += 1;
+        let key = format!("topic:{}", hash);
+        let slice = match store.get(&key).await? {
+            Some(slice) => slice,
+            None => {
+                report.problems.push(Problem::MissingKey {
+                    set: "topics".to_string(),
+                    member: hash,
+                });
+                continue;
+            }
+        };
+        match serde_json::from_slice::<Topic>(&slice) {
+            Ok(topic) => {
+                if !hashing::verify(&topic, &hash) {
+                    report.problems.push(Problem::HashMismatch {
+                        key,
+                        computed: topic.hash(),
+                    });
+                }
+            }
+            Err(e) => report.problems.push(Problem::Unreadable {
+                key,
+                error: e.to_string(),
+            }),
+        }
+    }
+
+    for hash in store.set_members("versions").await? {
+        report.versions += 1;
+        let key = format!("version:{}", hash);
+        let slice = match store.get(&key).await? {
+            Some(slice) => slice,
+            None => {
+                report.problems.push(Problem::MissingKey {
+                    set: "versions".to_string(),
+                    member: hash,
+                });
+                continue;
+            }
+        };
+        match serde_json::from_slice::<TopicVersion>(&slice) {
+            Ok(version) => {
+                let computed = version.hash();
+                if computed != hash {
+                    report
+                        .problems
+                        .push(Problem::HashMismatch { key, computed });
+                }
+            }
+            Err(e) => report.problems.push(Problem::Unreadable {
+                key,
+                error: e.to_string(),
+            }),
+        }
+    }
+
+    for id in store.set_members("headers").await? {
+        report.headers += 1;
+
+        let history: Vec<String> = store
+            .list_range(&format!("history:{}", id), 0, -1)
+            .await?
+            .iter()
+            .map(|e| HistoryEntry::parse(e).hash)
+            .collect();
+        if !history.is_empty() {
+            report.histories += 1;
+        }
+        for hash in &history {
+            if store.get(&format!("topic:{}", hash)).await?.is_none() {
+                report.problems.push(Problem::MissingTopic {
+                    id: id.to_string(),
+                    hash: hash.to_string(),
+                });
+            }
+        }
+
+        let key = format!("header:{}", id);
+        let slice = match store.get(&key).await? {
+            Some(slice) => slice,
+            None => {
+                report.problems.push(Problem::MissingKey {
+                    set: "headers".to_string(),
+                    member: id,
+                });
+                continue;
+            }
+        };
+        match serde_json::from_slice::<TopicHeader>(&slice) {
+            Ok(header) => match history.last() {
+                Some(latest) if latest != &header.hash => {
+                    report.problems.push(Problem::StaleHeader {
+                        id,
+                        header: header.hash,
+                        latest: latest.to_string(),
+                    })
+                }
+                Some(_) => {}
+                None if store
+                    .get(&format!("topic:{}", header.hash))
+                    .await?
+                    .is_some() =>
+                {
+                    report.without_history.push(id)
+                }
+                None => report.problems.push(Problem::MissingTopic {
+                    id,
+                    hash: header.hash,
+                }),
+            },
+            Err(e) => report.problems.push(Problem::Unreadable {
+                key,
+                error: e.to_string(),
+            }),
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memory_store::MemoryStore, redis_util};
+
+    #[actix_rt::test]
+    async fn topics_without_history_are_not_problems() {
+        let store = MemoryStore::default();
+        let topic = Topic::new("t", "d");
+        let hash = redis_util::add(&topic, &store).await.unwrap();
+        let header = TopicHeader::new(&topic.id, &hash, "t", None);
+        redis_util::add(&header, &store).await.unwrap();
+
+        let report = check(&store).await.unwrap();
+        assert!(report.is_clean());
+        assert_eq!(report.without_history, vec![topic.id.to_string()]);
+
+        // unless their topic is gone
+        let lost = TopicHeader::new(&uuid::Uuid::new_v4(), "nothing", "t", None);
+        redis_util::add(&lost, &store).await.unwrap();
+
+        let report = check(&store).await.unwrap();
+        assert!(matches!(
+            report.problems.as_slice(),
+            [Problem::MissingTopic { .. }]
+        ));
+    }
+}
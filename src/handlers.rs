@@ -1,208 +1,427 @@
 use crate::{
+    archive, chain, diff,
+    error::DumpError,
+    fsck, hashing,
     ipfs::post_ipfs,
-    model::{RepresentativeInfo, TopicCalculationResult, TopicHeader},
-    redis_util, RedisObject,
+    model::{
+        Aggregation, Change, Fork, Operation, RepresentativeInfo, State, Topic,
+        TopicCalculationResult, TopicHeader,
+    },
+    operations::{self, Op},
+    redis_util,
+    store::Store,
+    validation, RedisObject,
 };
-use actix::Addr;
-use actix_redis::RedisActor;
-use actix_web::{get, post, web, Responder};
+use actix_web::{get, http::header, post, web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
 use futures::{future::join_all, join};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::collections::BTreeMap;
 use uuid::Uuid;
-use vote::{TopicData, VoteData};
 
 #[get("/hello/")]
 pub async fn hello() -> impl Responder {
     "hello".to_string()
 }
 
-#[get("/list/")]
-pub async fn get_list(redis: web::Data<Addr<RedisActor>>) -> impl Responder {
-    let ids = redis_util::get_list("header", &redis).await;
+/// 200 while the store is reachable, 503 otherwise
+#[get("/health/")]
+pub async fn health(store: web::Data<dyn Store>) -> Result<HttpResponse, DumpError> {
+    store.ping().await?;
+    Ok(HttpResponse::Ok().json(json!({"status":"ok"})))
+}
 
-    if ids.is_none() {
-        return web::Json(json!({"status":"error", "mes":"could not get tag list"}));
-    };
+#[derive(Debug, Deserialize)]
+pub struct ListQuery {
+    /// only topics in this state, archived ones included
+    state: Option<State>,
+}
+
+/// Every topic but the archived ones, or with `?state=` only those in that state.
+#[get("/list/")]
+pub async fn get_list(
+    query: web::Query<ListQuery>,
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
+    let ids = redis_util::get_list("header", store.as_ref()).await?;
 
     log::info!("{:?}", &ids);
 
-    let tags: Vec<TopicHeader> = join_all(
-        ids.unwrap()
-            .iter()
-            .map(|tag| redis_util::get_slice(tag, "header", &redis)),
+    let slices = join_all(
+        ids.iter()
+            .map(|tag| redis_util::get_slice(tag, "header", store.as_ref())),
     )
-    .await
-    .iter()
-    .filter(|result| result.is_some())
-    .map(|tag| serde_json::from_slice(&(tag.to_owned().unwrap())).unwrap())
-    .collect();
+    .await;
+
+    let mut tags: Vec<TopicHeader> = Vec::new();
+    for slice in slices {
+        if let Some(slice) = slice? {
+            let tag: TopicHeader = serde_json::from_slice(&slice)?;
+            let listed = match query.state {
+                Some(state) => tag.state == state,
+                None => tag.state != State::Archived,
+            };
+            if listed {
+                tags.push(tag);
+            }
+        }
+    }
 
-    web::Json(serde_json::to_value(tags).unwrap())
+    Ok(HttpResponse::Ok().json(tags))
 }
 
 #[get("/header/{id}")]
 pub async fn get_header(
     id: web::Path<String>,
-    redis: web::Data<Addr<RedisActor>>,
-) -> impl Responder {
-    match redis_util::get_slice(&id, "header", &redis)
-        .await
-        .and_then(|slice| serde_json::from_slice::<TopicHeader>(&slice).ok())
-    {
-        Some(th) => web::Json(json!(th)),
-        None => web::Json(json!({"status":"errro", "mes": "error fetching header"})),
-    }
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
+    let header: TopicHeader = redis_util::get_object(&id, "header", store.as_ref()).await?;
+    Ok(HttpResponse::Ok().json(header))
+}
+
+/// entries per page when no `limit` is given
+const HISTORY_PAGE: usize = 50;
+const HISTORY_PAGE_MAX: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryQuery {
+    offset: Option<usize>,
+    limit: Option<usize>,
 }
 
+/// Newest first, `HISTORY_PAGE` entries from `offset` (0 by default) unless
+/// `limit` says otherwise, never more than `HISTORY_PAGE_MAX`.
 #[get("/history/{id}/")]
-pub async fn history(id: web::Path<String>, redis: web::Data<Addr<RedisActor>>) -> impl Responder {
+pub async fn history(
+    id: web::Path<String>,
+    query: web::Query<HistoryQuery>,
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
     let id = id.into_inner();
-    let history = redis_util::get_history(&id, &redis).await;
 
-    match history {
-        Some(h) => web::Json(serde_json::to_value(h).unwrap()),
-        None => web::Json(json!({"status":"error", "mes":"history not found"})),
-    }
+    let offset = query.offset.unwrap_or(0);
+    let limit = query.limit.unwrap_or(HISTORY_PAGE).min(HISTORY_PAGE_MAX);
+    let history = redis_util::get_history_page(&id, offset, limit, store.as_ref()).await?;
+
+    Ok(HttpResponse::Ok().json(history))
+}
+
+#[get("/history/{id}/count/")]
+pub async fn history_count(
+    id: web::Path<String>,
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
+    let count = redis_util::count_history(&id, store.as_ref()).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"ok", "id": id.into_inner(), "count": count})))
+}
+
+/// Walks the version chain of a topic back to its first version, see `chain`.
+#[get("/topic/chain/{id}/")]
+pub async fn validate_chain(
+    id: web::Path<String>,
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
+    let report = chain::validate(&id, store.as_ref()).await?;
+
+    Ok(HttpResponse::Ok()
+        .json(json!({"status":"ok", "valid": report.is_valid(), "report": report})))
 }
 
 #[post("/topic/raw/")]
 pub async fn post_topic_raw(
-    topic: web::Json<TopicData>,
-    redis: web::Data<Addr<RedisActor>>,
-) -> impl Responder {
-    let topic: TopicData = topic.into_inner();
+    req: HttpRequest,
+    topic: web::Json<Topic>,
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
+    let topic: Topic = topic.into_inner();
+
+    validation::ok(validation::check_topic(&topic))?;
 
     // check if uuid is alreay in tags
-    let header: Option<TopicHeader> =
-        redis_util::get_slice(&topic.id.to_string(), "header", &redis)
-            .await
-            .and_then(|v| serde_json::from_slice(&v).unwrap());
+    let previous = redis_util::get_slice(&topic.id.to_string(), "header", store.as_ref()).await?;
+    let header: Option<TopicHeader> = match &previous {
+        Some(v) => Some(serde_json::from_slice(v)?),
+        None => None,
+    };
 
     let topic_hash = topic.hash();
 
-    let new_header = match header {
-        Some(t) if t.hash == topic_hash => {
+    if let Some(t) = header {
+        if let Some(expected) = if_match(&req) {
+            if expected != t.hash {
+                return Err(DumpError::Conflict { hash: t.hash });
+            }
+        }
+
+        if t.state == State::Draft && !topic.votes.is_empty() {
+            return Err(DumpError::State(format!(
+                "topic {} is a draft, it doesn't take votes",
+                t.id
+            )));
+        }
+
+        if hashing::verify(&topic, &t.hash) {
             // check if tag's hash is the same as the current topic
             // if it's the same do nothing. data won't change
-            return web::Json(
+            return Ok(HttpResponse::Ok().json(
                 json!({"status":"ok", "hash":t.hash, "id": t.id, "mes":"dup found, no change"}),
-            );
+            ));
         }
-        _ => TopicHeader::new(&topic.id, &topic_hash, &topic.title),
-    };
+    }
 
     let data = serde_json::to_value(&topic_hash)
         .expect("this is just a string... should be straight forward");
 
     let write_hash = post_ipfs(&data);
-    let push_history = redis_util::push_history(&topic.id, &topic_hash, &redis);
-    let update_tag = redis_util::add(&new_header, &redis);
-    let add_topic = redis_util::add(&topic, &redis);
-    let (id, _hash, _history, _ipfs) = join!(update_tag, add_topic, push_history, write_hash);
-    match id {
-        Some(id) => return web::Json(json!({"status":"ok", "hash": topic_hash, "id": id})),
-        None => {
-            return web::Json(
-                json!({"status":"error", "mes":"could not update tag and post topic."}),
-            )
-        }
-    }
+    let operation = match previous {
+        Some(_) => Operation::Posted,
+        None => Operation::Created,
+    };
+    let change = Change::new(operation, actor(&req));
+
+    let commit = redis_util::commit_topic(&topic, previous.as_deref(), &change, store.as_ref());
+    let (commit, _ipfs) = join!(commit, write_hash);
+    commit?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"ok", "hash": topic_hash, "id": topic.id})))
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct PartialTopic {
     title: String,
     description: String,
+    /// `draft` to set the topic up before opening it, open by default
+    #[serde(default)]
+    state: State,
 }
 
 #[post("/topic/new/")]
 pub async fn make_new_topic(
+    req: HttpRequest,
     partial: web::Json<PartialTopic>,
-    redis: web::Data<Addr<RedisActor>>,
-) -> impl Responder {
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
     let partial = partial.into_inner();
 
-    let new_topic = TopicData::new(&partial.title, &partial.description);
-    let new_header = TopicHeader::new(&new_topic.id, &new_topic.hash(), &partial.title);
+    let new_topic = Topic::new(&partial.title, &partial.description);
 
-    let (hash, _head) = join!(
-        redis_util::add(&new_topic, &redis),
-        redis_util::add(&new_header, &redis)
-    );
-
-    match hash {
-        Some(h) => web::Json(json!({"status":"ok", "id":new_topic.id, "hash":h})),
-        None => web::Json(json!({"status":"error", "mes": "error adding new topic"})),
+    let change = Change::new(Operation::Created, actor(&req));
+    match partial.state {
+        State::Draft | State::Open => {}
+        state => {
+            return Err(DumpError::Validation(format!(
+                "new topics are draft or open, not {:?}",
+                state
+            )))
+        }
     }
+
+    redis_util::commit_new_topic(&new_topic, partial.state, &change, store.as_ref()).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"ok", "id":new_topic.id, "hash":new_topic.hash()})))
 }
 
 #[post("/topic/update/{id}/{field}/")]
 pub async fn update_field(
+    req: HttpRequest,
     path: web::Path<(String, String)>,
     new_info: web::Json<String>,
-    redis: web::Data<Addr<RedisActor>>,
-) -> impl Responder {
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
     let (id, field) = path.into_inner();
 
     let new = new_info.into_inner();
 
-    let data = get_latest_id(&id, &redis).await;
-
-    if data.is_none() {
-        return web::Json(json!({"status":"error", "mes": "could not find table data under id."}));
-    }
-
-    let mut data = data.unwrap();
+    let (mut data, base) = get_latest_id(&id, store.as_ref()).await?;
 
-    match field.as_ref() {
-        "title" => data.title = new,
-        "description" => data.description = new,
-        _ => return web::Json(json!({"status":"error", "mes": "invalid field"})),
+    let operation = match field.as_ref() {
+        "title" => {
+            data.title = new;
+            Operation::TitleChanged
+        }
+        "description" => {
+            data.description = new;
+            Operation::DescriptionChanged
+        }
+        _ => return Err(DumpError::Validation(format!("invalid field {}", field))),
     };
 
-    match update_topic_data(&data, &redis).await {
-        DumpStatus::UPDATE_SUCCESS => web::Json(json!({"status":"ok"})),
-        DumpStatus::TOPIC_DATA_NO_CHANGE => {
-            web::Json(json!({"status":"ok", "mes":"no change in data"}))
-        }
-        _ => web::Json(json!({"status":"error", "mes":"failed to update topic data."})),
-    }
+    let change = Change::new(operation, actor(&req));
+    let status = update_topic_data(
+        &data,
+        &base,
+        if_match(&req).as_deref(),
+        &change,
+        store.as_ref(),
+    )
+    .await?;
+    Ok(update_response(status))
 }
 
 #[post("/topic/update/{id}/policy/")]
 pub async fn add_policy(
+    req: HttpRequest,
     id: web::Path<String>,
     policy: web::Json<String>,
-    redis: web::Data<Addr<RedisActor>>,
-) -> impl Responder {
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
     let id = id.into_inner();
     let policy = policy.into_inner();
 
-    let data = get_latest_id(&id, &redis).await;
+    let (mut data, base) = get_latest_id(&id, store.as_ref()).await?;
+
+    data.add_policy(&policy);
+
+    let change = Change::new(Operation::PolicyAdded, actor(&req));
+    let status = update_topic_data(
+        &data,
+        &base,
+        if_match(&req).as_deref(),
+        &change,
+        store.as_ref(),
+    )
+    .await?;
+    Ok(update_response(status))
+}
+
+/// Parses the `{policy}` segment of a path.
+fn policy_id(policy: &str) -> Result<Uuid, DumpError> {
+    Uuid::parse_str(policy)
+        .map_err(|_| DumpError::Validation(format!("invalid policy id {}", policy)))
+}
+
+#[post("/topic/update/{id}/policy/{policy}/rename/")]
+pub async fn rename_policy(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    name: web::Json<String>,
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
+    let (id, policy) = path.into_inner();
+    let op = Op::RenamePolicy {
+        policy: policy_id(&policy)?,
+        name: name.into_inner(),
+    };
+
+    apply_op(&req, &id, &op, actor(&req), store.as_ref()).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RemoveQuery {
+    /// spread the removed policy's weight over the voters' other choices
+    /// instead of dropping it
+    redistribute: Option<bool>,
+}
+
+#[post("/topic/update/{id}/policy/{policy}/remove/")]
+pub async fn remove_policy(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    query: web::Query<RemoveQuery>,
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
+    let (id, policy) = path.into_inner();
+    let op = Op::RemovePolicy {
+        policy: policy_id(&policy)?,
+        redistribute: query.redistribute.unwrap_or(false),
+    };
+
+    apply_op(&req, &id, &op, actor(&req), store.as_ref()).await
+}
+
+/// Takes every policy id of the topic, in the new display order.
+#[post("/topic/update/{id}/policies/order/")]
+pub async fn reorder_policies(
+    req: HttpRequest,
+    id: web::Path<String>,
+    order: web::Json<Vec<Uuid>>,
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
+    let op = Op::ReorderPolicies {
+        order: order.into_inner(),
+    };
+
+    apply_op(&req, &id, &op, actor(&req), store.as_ref()).await
+}
+
+/// Sets how results of the topic are computed, `null` for the default.
+#[post("/topic/update/{id}/aggregation/")]
+pub async fn set_aggregation(
+    req: HttpRequest,
+    id: web::Path<String>,
+    aggregation: web::Json<Option<Aggregation>>,
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
+    let op = Op::SetAggregation {
+        aggregation: aggregation.into_inner(),
+    };
+
+    apply_op(&req, &id, &op, actor(&req), store.as_ref()).await
+}
 
-    if data.is_none() {
-        return web::Json(json!({"status":"error", "mes":"failed to get topic data"}));
+/// Applies the ops in order and stores the outcome as a single version. If
+/// any of them fails nothing is stored.
+#[post("/topic/update/{id}/batch/")]
+pub async fn batch_update(
+    req: HttpRequest,
+    id: web::Path<String>,
+    ops: web::Json<Vec<Op>>,
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
+    let ops = ops.into_inner();
+    if ops.is_empty() {
+        return Err(DumpError::Validation("no operations in batch".to_string()));
     }
 
-    let mut data = data.unwrap();
+    let (mut data, base) = get_latest_id(&id, store.as_ref()).await?;
 
-    let _id = data.add_new_policy(&policy);
+    let state = serde_json::from_slice::<TopicHeader>(&base)?.state;
+    if let Some(op) = ops.iter().find(|op| !state.allows(op.operation())) {
+        return Err(DumpError::State(format!(
+            "topic {} is {:?}, it doesn't take {:?}",
+            id,
+            state,
+            op.operation()
+        )));
+    }
 
-    let vote: VoteData = data.to_owned().into();
+    let added = operations::apply_all(&mut data, &ops)?;
 
-    println!("vote:{:?}", vote);
+    let change = Change::new(Operation::Batch, actor(&req));
+    let status = update_topic_data(
+        &data,
+        &base,
+        if_match(&req).as_deref(),
+        &change,
+        store.as_ref(),
+    )
+    .await?;
 
-    match update_topic_data(&data, &redis).await {
-        DumpStatus::UPDATE_SUCCESS => web::Json(json!({"status":"ok"})),
-        DumpStatus::TOPIC_DATA_NO_CHANGE => {
-            web::Json(json!({"status":"ok", "mes":"no change in data"}))
-        }
-        _ => web::Json(json!({"status":"error", "mes":"failed to update topic data."})),
+    match status {
+        DumpStatus::UPDATE_SUCCESS => Ok(HttpResponse::Ok()
+            .json(json!({"status":"ok", "hash": data.hash(), "added_policies": added}))),
+        DumpStatus::TOPIC_DATA_NO_CHANGE => Ok(update_response(status)),
     }
 }
 
+/// Applies a single op to the latest version of topic `id` and stores the outcome.
+async fn apply_op(
+    req: &HttpRequest,
+    id: &str,
+    op: &Op,
+    actor: Option<Uuid>,
+    store: &dyn Store,
+) -> Result<HttpResponse, DumpError> {
+    let (mut data, base) = get_latest_id(id, store).await?;
+    operations::apply(&mut data, op)?;
+
+    let change = Change::new(op.operation(), actor).about(op.subject());
+    let status = update_topic_data(&data, &base, if_match(req).as_deref(), &change, store).await?;
+    Ok(update_response(status))
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct UserVote {
     id: Uuid,
@@ -212,212 +431,516 @@ pub struct UserVote {
 
 #[post("/topic/update/{id}/delegate/")]
 pub async fn update_vote(
+    req: HttpRequest,
     path: web::Path<String>,
     uservote: web::Json<UserVote>,
-    redis: web::Data<Addr<RedisActor>>,
-) -> impl Responder {
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
     let id = path.into_inner();
     let uservote = uservote.into_inner();
 
-    let data = get_latest_id(&id, &redis).await;
+    let (mut data, base) = get_latest_id(&id, store.as_ref()).await?;
+
+    validation::ok(validation::check_vote(
+        &data,
+        &uservote.id,
+        &uservote.vote,
+        "vote",
+    ))?;
 
-    if data.is_none() {
-        return web::Json(json!({ "status":"error", "mes":"could not find topic data." }));
+    // we want to update the delegates info for what ever reason (name change);
+    data.set_delegate(&uservote.id, &uservote.name);
+
+    data.set_vote(&uservote.id, uservote.vote);
+
+    let change = Change::new(Operation::VoteOverwritten, Some(uservote.id));
+    let status = update_topic_data(
+        &data,
+        &base,
+        if_match(&req).as_deref(),
+        &change,
+        store.as_ref(),
+    )
+    .await?;
+    Ok(update_response(status))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WithdrawQuery {
+    /// spread the weight others delegated to the voter over their other
+    /// choices instead of dropping it
+    redistribute: Option<bool>,
+}
+
+/// Takes a voter out of a topic: their vote, their place among the delegates
+/// and every delegation pointing at them. Weight delegated to them is dropped,
+/// unless `?redistribute=true`.
+#[post("/topic/update/{id}/delegate/{voter}/withdraw/")]
+pub async fn withdraw_voter(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    query: web::Query<WithdrawQuery>,
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
+    let (id, voter) = path.into_inner();
+    let voter = Uuid::parse_str(&voter)
+        .map_err(|_| DumpError::Validation(format!("invalid voter id {}", voter)))?;
+    let op = Op::RemoveVoter {
+        voter,
+        redistribute: query.redistribute.unwrap_or(false),
     };
 
-    let mut data = data.unwrap();
+    apply_op(&req, &id, &op, actor(&req), store.as_ref()).await
+}
 
-    // we want to update the delegates info for what ever reason (name change);
-    data.force_add_delegate(&uservote.id, &uservote.name);
+/// Moves a topic to the state in the body: `open`, `closed` or `archived`.
+#[post("/topic/state/{id}/")]
+pub async fn set_state(
+    req: HttpRequest,
+    id: web::Path<String>,
+    state: web::Json<State>,
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
+    let base = redis_util::get_slice(&id, "header", store.as_ref())
+        .await?
+        .ok_or_else(|| DumpError::NotFound(format!("topic {}", id)))?;
+
+    let current: TopicHeader = serde_json::from_slice(&base)?;
+    if let Some(expected) = if_match(&req) {
+        if expected != current.hash {
+            return Err(DumpError::Conflict { hash: current.hash });
+        }
+    }
 
-    data.overwrite_vote_for(uservote.id, uservote.vote);
+    let header = redis_util::set_state(&base, state.into_inner(), store.as_ref()).await?;
 
-    match update_topic_data(&data, &redis).await {
-        DumpStatus::UPDATE_SUCCESS => web::Json(json!({"status":"ok"})),
-        DumpStatus::TOPIC_DATA_NO_CHANGE => {
-            web::Json(json!({"status":"ok", "mes":"no change in data"}))
+    Ok(HttpResponse::Ok().json(json!({"status":"ok", "header": header})))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Schedule {
+    opens_at: Option<DateTime<Utc>>,
+    closes_at: Option<DateTime<Utc>>,
+}
+
+/// Sets when a topic opens and closes by itself, see `deadlines`. Once
+/// `closes_at` passes, edits are refused and the topic gets closed with its result.
+#[post("/topic/schedule/{id}/")]
+pub async fn set_schedule(
+    id: web::Path<String>,
+    schedule: web::Json<Schedule>,
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
+    let base = redis_util::get_slice(&id, "header", store.as_ref())
+        .await?
+        .ok_or_else(|| DumpError::NotFound(format!("topic {}", id)))?;
+
+    let schedule = schedule.into_inner();
+    let header =
+        redis_util::set_schedule(&base, schedule.opens_at, schedule.closes_at, store.as_ref())
+            .await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"ok", "header": header})))
+}
+
+/// Makes `hash`, an earlier version of the topic, its current version again.
+/// This is a new commit on top of the history, nothing is rewritten.
+#[post("/topic/revert/{id}/{hash}/")]
+pub async fn revert_topic(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
+    let (id, hash) = path.into_inner();
+
+    let entries = redis_util::get_history(&id, store.as_ref()).await?;
+    if !entries.iter().any(|e| e.hash == hash) {
+        return Err(DumpError::Validation(format!(
+            "{} is not in the history of topic {}",
+            hash, id
+        )));
+    }
+
+    let (_, base) = get_latest_id(&id, store.as_ref()).await?;
+    let data: Topic = redis_util::get_object(&hash, "topic", store.as_ref()).await?;
+
+    let change = Change::new(Operation::Reverted, actor(&req));
+    let status = update_topic_data(
+        &data,
+        &base,
+        if_match(&req).as_deref(),
+        &change,
+        store.as_ref(),
+    )
+    .await?;
+    Ok(update_response(status))
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ForkOptions {
+    /// version to fork, from the topic's history, the current one if missing
+    hash: Option<String>,
+    /// whether the votes come along, they do by default
+    keep_votes: Option<bool>,
+    /// title of the fork, the parent's if missing
+    title: Option<String>,
+}
+
+/// Copies a topic under a new uuid, remembering which version of which topic
+/// it came from.
+#[post("/topic/fork/{id}/")]
+pub async fn fork_topic(
+    req: HttpRequest,
+    id: web::Path<String>,
+    body: web::Bytes,
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
+    let id = id.into_inner();
+
+    // the body is optional, an empty one forks the current version as is
+    let options: ForkOptions = if body.is_empty() {
+        ForkOptions::default()
+    } else {
+        serde_json::from_slice(&body).map_err(|e| DumpError::Validation(e.to_string()))?
+    };
+
+    let hash = match options.hash {
+        Some(hash) => {
+            let entries = redis_util::get_history(&id, store.as_ref()).await?;
+            if !entries.iter().any(|e| e.hash == hash) {
+                return Err(DumpError::Validation(format!(
+                    "{} is not in the history of topic {}",
+                    hash, id
+                )));
+            }
+            hash
+        }
+        None => hash_at(&id, None, store.as_ref()).await?,
+    };
+
+    let mut data: Topic = redis_util::get_object(&hash, "topic", store.as_ref()).await?;
+    let parent = data.id;
+
+    data.id = Uuid::new_v4();
+    if let Some(title) = options.title {
+        data.title = title;
+    }
+    if !options.keep_votes.unwrap_or(true) {
+        data.votes.clear();
+    }
+
+    let fork = Fork::new(&data.id, &parent, &hash);
+    let change = Change::new(Operation::Forked, actor(&req));
+    redis_util::commit_fork(&data, &fork, &change, store.as_ref()).await?;
+
+    Ok(HttpResponse::Ok()
+        .json(json!({"status":"ok", "id": data.id, "hash": data.hash(), "parent": fork})))
+}
+
+/// Where a topic was forked from, all the way up, and its direct forks.
+#[get("/topic/lineage/{id}/")]
+pub async fn lineage(
+    id: web::Path<String>,
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
+    let id = id.into_inner();
+
+    // make sure the topic exists
+    let _: TopicHeader = redis_util::get_object(&id, "header", store.as_ref()).await?;
+
+    let mut ancestors: Vec<Fork> = Vec::new();
+    let mut next = id.to_string();
+    while let Some(fork) = redis_util::get_fork(&next, store.as_ref()).await? {
+        next = fork.parent.to_string();
+        // forks only ever point at older topics, but don't trust the store with a loop
+        if next == id || ancestors.iter().any(|a| a.id == fork.parent) {
+            break;
         }
-        _ => web::Json(json!({"status":"error", "mes":"failed to update topic data."})),
+        ancestors.push(fork);
     }
+
+    let mut forks = Vec::new();
+    for child in redis_util::get_children(&id, store.as_ref()).await? {
+        if let Some(fork) = redis_util::get_fork(&child, store.as_ref()).await? {
+            forks.push(fork);
+        }
+    }
+
+    Ok(HttpResponse::Ok()
+        .json(json!({"status":"ok", "id": id, "ancestors": ancestors, "forks": forks})))
+}
+
+/// The hash the client based its edit on, from the `If-Match` header.
+fn if_match(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(header::IF_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().trim_matches('"').to_string())
+        .filter(|v| v != "*")
 }
 
-pub async fn get_latest_id(id: &str, redis: &web::Data<Addr<RedisActor>>) -> Option<TopicData> {
-    let hash = redis_util::get_slice(id, "header", &redis)
-        .await
-        .and_then(|slice| serde_json::from_slice::<TopicHeader>(&slice).ok())
-        .and_then(|header| Some(header.hash));
+/// The voter making the request, from the optional `X-Voter` header.
+fn actor(req: &HttpRequest) -> Option<Uuid> {
+    req.headers()
+        .get("X-Voter")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| Uuid::parse_str(v.trim()).ok())
+}
 
-    if hash.is_none() {
-        return None;
+fn update_response(status: DumpStatus) -> HttpResponse {
+    match status {
+        DumpStatus::UPDATE_SUCCESS => HttpResponse::Ok().json(json!({"status":"ok"})),
+        DumpStatus::TOPIC_DATA_NO_CHANGE => {
+            HttpResponse::Ok().json(json!({"status":"ok", "mes":"no change in data"}))
+        }
     }
+}
+
+/// Fetches the latest version of a topic, along with the raw header it was found
+/// through. Hand the header back to `update_topic_data` when writing the edit.
+pub async fn get_latest_id(id: &str, store: &dyn Store) -> Result<(Topic, Vec<u8>), DumpError> {
+    let base = redis_util::get_slice(id, "header", store)
+        .await?
+        .ok_or_else(|| DumpError::NotFound(format!("topic {}", id)))?;
 
-    let hash = hash.unwrap();
+    let hash = serde_json::from_slice::<TopicHeader>(&base)?.hash;
 
-    redis_util::get_slice(&hash, "topic", &redis)
-        .await
-        .and_then(|slice| serde_json::from_slice::<TopicData>(&slice).ok())
+    let data = redis_util::get_object(&hash, "topic", store).await?;
+    Ok((data, base))
 }
 
 pub enum DumpStatus {
     TOPIC_DATA_NO_CHANGE,
-    HEADER_NOT_FOUND,
     UPDATE_SUCCESS,
-    UPDATE_FAILED,
 }
 
+/// Stores `data` as the new version of its topic, provided the header is still
+/// `base`, i.e. nobody else committed a version since `data` was read.
+/// `expected` is the hash the client says it based its edit on, if it told us.
 pub async fn update_topic_data(
-    data: &TopicData,
-    redis: &web::Data<Addr<RedisActor>>,
-) -> DumpStatus {
-    let header: Option<TopicHeader> = redis_util::get_slice(&data.id.to_string(), "header", &redis)
-        .await
-        .and_then(|slice| serde_json::from_slice(&slice).ok());
+    data: &Topic,
+    base: &[u8],
+    expected: Option<&str>,
+    change: &Change,
+    store: &dyn Store,
+) -> Result<DumpStatus, DumpError> {
+    let header: TopicHeader = serde_json::from_slice(base)?;
 
     log::info!("header:{:?}", header);
 
-    if header.is_none() {
-        // no header, we don't update
-        return DumpStatus::HEADER_NOT_FOUND;
-    }
-
     let data_hash = data.hash();
-    let header = header.unwrap();
+
+    if expected.map_or(false, |e| e != header.hash) {
+        return Err(DumpError::Conflict { hash: header.hash });
+    }
 
     log::info!("{}={}", &header.hash, &data_hash);
 
-    if &header.hash == &data_hash {
+    if hashing::verify(data, &header.hash) {
         // same data, we don't update
-        return DumpStatus::TOPIC_DATA_NO_CHANGE;
+        return Ok(DumpStatus::TOPIC_DATA_NO_CHANGE);
     }
 
-    let push_history = redis_util::push_history(&data.id, &data_hash, &redis);
-    let new_header = TopicHeader::new(&data.id, &data.hash(), &data.title);
-    let update_header = redis_util::add(&new_header, &redis);
-    let post_data = redis_util::add(data, &redis);
-
-    let (_, _, hash) = join!(push_history, update_header, post_data);
+    redis_util::commit_topic(data, Some(base), change, store).await?;
 
-    log::info!("hash: {:?}", &hash);
-
-    match hash {
-        Some(_h) => DumpStatus::UPDATE_SUCCESS,
-        _ => DumpStatus::UPDATE_FAILED,
-    }
+    Ok(DumpStatus::UPDATE_SUCCESS)
 }
 
 #[get("/topic/raw/{hash}/")]
 pub async fn get_topic_raw(
     hash: web::Path<String>,
-    redis: web::Data<Addr<RedisActor>>,
-) -> impl Responder {
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
     let hash = hash.into_inner();
-    match redis_util::get_slice(&hash, "topic", &redis).await {
-        Some(s) => {
-            let topic: TopicData =
-                serde_json::from_slice(&s).expect("topic should be Deserializeable");
-            web::Json(serde_json::to_value(topic).expect("this also should be Serializable"))
-        }
-        None => web::Json(json!({"status":  "error", "message": "could not find topic"})),
-    }
+    let topic: Topic = redis_util::get_object(&hash, "topic", store.as_ref()).await?;
+
+    Ok(HttpResponse::Ok().json(topic))
+}
+
+/// What changed going from version `a` to version `b`, see `diff`.
+#[get("/topic/diff/{a}/{b}/")]
+pub async fn diff_topics(
+    path: web::Path<(String, String)>,
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
+    let (a, b) = path.into_inner();
+
+    let from = redis_util::get_object::<Topic>(&a, "topic", store.as_ref());
+    let to = redis_util::get_object::<Topic>(&b, "topic", store.as_ref());
+    let (from, to) = join!(from, to);
+
+    let diff = diff::diff(&from?, &to?);
+
+    Ok(HttpResponse::Ok().json(json!({"status":"ok", "from": a, "to": b, "diff": diff})))
 }
 
 #[get("/topic/{id}/")]
 pub async fn get_topic_by_id(
     id: web::Path<String>,
-    redis: web::Data<Addr<RedisActor>>,
-) -> impl Responder {
+    query: web::Query<AtQuery>,
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
     let id = id.into_inner();
 
-    let latest_hash = redis_util::get_slice(&id, "header", &redis)
-        .await
-        .and_then(|header| {
-            Some(
-                serde_json::from_slice::<TopicHeader>(&header)
-                    .expect("Topic Header is Serializable"),
-            )
-        })
-        .and_then(|h| Some(h.hash));
-
-    match latest_hash {
-        Some(s) => {
-            let topic = redis_util::get_slice(&s, "topic", &redis)
-                .await
-                .and_then(|b| Some(serde_json::from_slice::<TopicData>(&b).unwrap()));
-            match topic {
-                Some(t) => web::Json(json!(t)),
-                None => web::Json(json!({"status":  "error", "message": "could not find topic"})),
-            }
-        }
-        None => web::Json(json!({"status":  "error", "message": "could not find topic"})),
+    if let Some(at) = &query.at {
+        let entry = redis_util::version_at(&id, at, store.as_ref()).await?;
+        let topic: Topic = redis_util::get_object(&entry.hash, "topic", store.as_ref()).await?;
+        return Ok(HttpResponse::Ok().json(topic));
+    }
+
+    let latest: TopicHeader = redis_util::get_object(&id, "header", store.as_ref()).await?;
+    let topic: Topic = redis_util::get_object(&latest.hash, "topic", store.as_ref()).await?;
+
+    // clients send the ETag back as If-Match when they edit the topic
+    Ok(HttpResponse::Ok()
+        .header(header::ETAG, format!("\"{}\"", latest.hash))
+        .json(topic))
+}
+
+/// `?at=<rfc3339>` asks for whatever was current at that moment instead of now.
+#[derive(Debug, Deserialize)]
+pub struct AtQuery {
+    at: Option<DateTime<Utc>>,
+}
+
+/// The hash of the version of `id` current at `at`, or right now.
+async fn hash_at(
+    id: &str,
+    at: Option<&DateTime<Utc>>,
+    store: &dyn Store,
+) -> Result<String, DumpError> {
+    match at {
+        Some(at) => Ok(redis_util::version_at(id, at, store).await?.hash),
+        None => Ok(redis_util::get_object::<TopicHeader>(id, "header", store)
+            .await?
+            .hash),
     }
 }
 
+/// The `VoteData` of a topic, now or `?at=` some point in time.
+#[get("/topic/vote/{id}/")]
+pub async fn get_vote_by_id(
+    id: web::Path<String>,
+    query: web::Query<AtQuery>,
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
+    let hash = hash_at(&id, query.at.as_ref(), store.as_ref()).await?;
+    let topic: Topic = redis_util::get_object(&hash, "topic", store.as_ref()).await?;
+    let vote = topic.vote_data()?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"ok", "hash": hash, "vote": vote})))
+}
+
 #[post("/result/{hash}/")]
 pub async fn dump_result(
     hash: web::Path<String>,
     data: web::Json<serde_json::Value>,
-    redis: web::Data<Addr<RedisActor>>,
-) -> impl Responder {
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
     let hash = hash.into_inner();
     let data = data.into_inner();
     let result = TopicCalculationResult::new(&hash, &data);
 
-    match redis_util::add(&result, &redis).await {
-        Some(hash) => web::Json(json!({"status":"ok", "hash": hash})),
-        None => web::Json(json!({"status":"error", "mes": "could not add result"})),
-    }
+    let hash = redis_util::add_result(&result, store.as_ref()).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"ok", "hash": hash})))
 }
 
 #[get("/result/{hash}/")]
 pub async fn get_result(
     hash: web::Path<String>,
-    redis: web::Data<Addr<RedisActor>>,
-) -> impl Responder {
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
     let hash = hash.into_inner();
 
-    match redis_util::get_slice(&hash, "result", &redis)
-        .await
-        .and_then(|bytes| serde_json::from_slice::<TopicCalculationResult>(&bytes).ok())
-    {
-        Some(result) => web::Json(json!(result)),
-        None => web::Json(json!({"status":"error", "mes":"could not find result"})),
-    }
+    let result: TopicCalculationResult =
+        redis_util::get_object(&hash, "result", store.as_ref()).await?;
+
+    Ok(HttpResponse::Ok().json(result))
+}
+
+/// The result for the version of a topic current now, or `?at=` some point in time.
+#[get("/result/topic/{id}/")]
+pub async fn get_result_by_id(
+    id: web::Path<String>,
+    query: web::Query<AtQuery>,
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
+    let hash = hash_at(&id, query.at.as_ref(), store.as_ref()).await?;
+    let result: TopicCalculationResult =
+        redis_util::get_object(&hash, "result", store.as_ref()).await?;
+
+    Ok(HttpResponse::Ok().json(result))
 }
 
 #[post("/rep/{rep_id}/")]
 pub async fn post_rep(
     rep_info: web::Json<RepresentativeInfo>,
     rep_id: web::Path<String>,
-    redis: web::Data<Addr<RedisActor>>,
-) -> impl Responder {
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
     let id = rep_id.into_inner();
     let rep_info = rep_info.into_inner();
 
-    // TODO: this needs to be validated weather it's really a uuid
+    Uuid::parse_str(&id)
+        .map_err(|_| DumpError::Validation(format!("representative id {} is not a uuid", id)))?;
 
-    match redis_util::push_representative(&id, &rep_info, &redis).await {
-        Some(_x) => web::Json(json!({"status":"ok"})),
-        None => web::Json(json!({"status":"error", "mes":"could not add reps"})),
-    }
+    redis_util::push_representative(&id, &rep_info, store.as_ref()).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"ok"})))
 }
 
 #[get("/rep/{rep_id}/")]
 pub async fn get_rep(
     rep_id: web::Path<String>,
-    redis: web::Data<Addr<RedisActor>>,
-) -> impl Responder {
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
     let id = rep_id.into_inner();
 
-    match redis_util::get_representative(&id, &redis).await {
-        Some(reps) => web::Json(json!(reps)),
-        None => web::Json(json!({"status":"error", "mes":"could not get rep"})),
-    }
+    let rep = redis_util::get_representative(&id, store.as_ref()).await?;
+
+    Ok(HttpResponse::Ok().json(rep))
 }
 
 #[get("/reps/")]
-pub async fn get_reps(redis: web::Data<Addr<RedisActor>>) -> impl Responder {
-    match redis_util::get_representatives(&redis).await {
-        Some(reps) => web::Json(json!(reps)),
-        None => web::Json(json!({"status":"error", "mes":"could not get reps"})),
-    }
+pub async fn get_reps(store: web::Data<dyn Store>) -> Result<HttpResponse, DumpError> {
+    let reps = redis_util::get_representatives(store.as_ref()).await?;
+
+    Ok(HttpResponse::Ok().json(reps))
+}
+
+#[get("/admin/export/")]
+pub async fn export(store: web::Data<dyn Store>) -> Result<HttpResponse, DumpError> {
+    let records = archive::export(store.as_ref()).await?;
+
+    let mut body = Vec::new();
+    archive::write_ndjson(&records, &mut body).map_err(|e| DumpError::Internal(e.to_string()))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .header(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"dump.ndjson\"",
+        )
+        .body(body))
+}
+
+/// Registered by hand in main, with a payload limit big enough for archives.
+pub async fn import(
+    body: web::Bytes,
+    store: web::Data<dyn Store>,
+) -> Result<HttpResponse, DumpError> {
+    let records = archive::read_ndjson(body.as_ref())?;
+    let summary = archive::import(records, store.as_ref()).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"ok", "imported": summary})))
+}
+
+/// Runs the integrity check, 200 either way with `clean` telling whether anything was found.
+#[get("/admin/fsck/")]
+pub async fn check(store: web::Data<dyn Store>) -> Result<HttpResponse, DumpError> {
+    let report = fsck::check(store.as_ref()).await?;
+
+    Ok(HttpResponse::Ok()
+        .json(json!({"status":"ok", "clean": report.is_clean(), "report": report})))
 }
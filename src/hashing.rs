@@ -0,0 +1,232 @@
+//! Content hashes of topics.
+//!
+//! A hash is the base58 of `[version, 0x12, 0x20, sha256...]`, a version byte
+//! followed by a sha2-256 multihash. The version says how the topic was turned
+//! into bytes before hashing:
+//!
+//! - `1`: compact JSON of `{"id","title","description","policies","delegates","votes"}`
+//!   in exactly that order. `policies` and `delegates` are `[[uuid, text], ...]`,
+//!   `votes` is `[[delegate, [[policy, value], ...]], ...]`, all sorted by uuid
+//!   (lowercase, hyphenated). Values are written the way serde_json writes an f64.
+//!   Nothing here goes through the `vote` crate's own serialization, so its
+//!   releases can't move the hash. When the policies were reordered,
+//!   `"policy_order": [uuid, ...]` follows `votes`, in display order; topics that
+//!   never were hash exactly as before the field existed. Likewise
+//!   `"aggregation": {"method","params":{"is_quadratic","is_normalize"}}` comes
+//!   last once one was set, params `null` when left at their default.
+//!
+//! Versions (`TopicVersion`) are hashed the same way, over the compact JSON of
+//! `{"topic","parent"}` with `parent` null for the first version.
+//!
+//! Hashes from before the versioning are a bare base58 sha256 (32 bytes once
+//! decoded) and are still accepted by `verify`, see `legacy_hash`.
+
+use crate::{
+    error::DumpError,
+    model::{Aggregation, Topic, TopicVersion},
+};
+use bs58::{decode, encode};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// what new hashes are computed with
+pub const HASH_VERSION: u8 = 1;
+
+/// multihash code and digest length for sha2-256
+const SHA2_256: [u8; 2] = [0x12, 0x20];
+
+#[derive(Serialize)]
+struct CanonicalTopic<'a> {
+    id: String,
+    title: &'a str,
+    description: &'a str,
+    policies: Vec<(String, &'a str)>,
+    delegates: Vec<(String, &'a str)>,
+    votes: Vec<(String, Vec<(String, f64)>)>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    policy_order: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aggregation: Option<&'a Aggregation>,
+}
+
+fn key(id: &Uuid) -> String {
+    id.to_hyphenated().to_string()
+}
+
+fn sorted<T>(mut entries: Vec<(String, T)>) -> Vec<(String, T)> {
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    entries
+}
+
+/// The bytes version 1 hashes.
+pub fn canonical_bytes(topic: &Topic) -> Vec<u8> {
+    let canonical = CanonicalTopic {
+        id: key(&topic.id),
+        title: &topic.title,
+        description: &topic.description,
+        policies: sorted(
+            topic
+                .policies
+                .iter()
+                .map(|(id, text)| (key(id), text.as_str()))
+                .collect(),
+        ),
+        delegates: sorted(
+            topic
+                .delegates
+                .iter()
+                .map(|(id, name)| (key(id), name.as_str()))
+                .collect(),
+        ),
+        votes: sorted(
+            topic
+                .votes
+                .iter()
+                .map(|(delegate, votes)| {
+                    let votes = votes.iter().map(|(p, v)| (key(p), *v)).collect();
+                    (key(delegate), sorted(votes))
+                })
+                .collect(),
+        ),
+        policy_order: topic.policy_order.iter().map(key).collect(),
+        aggregation: topic.aggregation.as_ref(),
+    };
+
+    serde_json::to_vec(&canonical).expect("canonical topic should be serializable")
+}
+
+fn multihash(canonical: &[u8]) -> String {
+    let digest = Sha256::digest(canonical);
+
+    let mut bytes = vec![HASH_VERSION];
+    bytes.extend_from_slice(&SHA2_256);
+    bytes.extend_from_slice(&digest);
+
+    encode(bytes).into_string()
+}
+
+pub fn topic_hash(topic: &Topic) -> String {
+    multihash(&canonical_bytes(topic))
+}
+
+pub fn version_hash(version: &TopicVersion) -> String {
+    #[derive(Serialize)]
+    struct CanonicalVersion<'a> {
+        topic: &'a str,
+        parent: Option<&'a str>,
+    }
+
+    let canonical = CanonicalVersion {
+        topic: &version.topic,
+        parent: version.parent.as_deref(),
+    };
+
+    multihash(&serde_json::to_vec(&canonical).expect("canonical version should be serializable"))
+}
+
+/// The hash topics were stored under before `HASH_VERSION` existed. It leaves
+/// out the uuids of policies and delegates and depends on how `VoteData`
+/// serializes, only kept around to verify old keys.
+pub fn legacy_hash(topic: &Topic) -> Result<String, DumpError> {
+    let data = topic.to_data()?;
+    let mut hash = Sha256::new();
+    hash.update(topic.title.as_bytes());
+    hash.update(topic.description.as_bytes());
+
+    let p_values = data
+        .policies_values()
+        .iter()
+        .fold("".to_string(), |acc, d| format!("{}{}", acc, d));
+    hash.update(p_values.as_bytes());
+    let d_values = data
+        .delegates_values()
+        .iter()
+        .fold("".to_string(), |acc, d| format!("{}{}", acc, d));
+    hash.update(d_values.as_bytes());
+    let vote = topic.vote_data()?;
+    hash.update(serde_json::to_vec(&vote).expect("VoteData should be Serializeable."));
+
+    Ok(encode(hash.finalize()).into_string())
+}
+
+/// Whether `hash` is the hash of `topic`, under whichever version it was made with.
+pub fn verify(topic: &Topic, hash: &str) -> bool {
+    let bytes = match decode(hash).into_vec() {
+        Ok(bytes) => bytes,
+        Err(_) => return false,
+    };
+
+    match bytes.len() {
+        32 => {
+            topic.policy_order.is_empty()
+                && topic.aggregation.is_none()
+                && legacy_hash(topic).map_or(false, |legacy| legacy == hash)
+        }
+        35 if bytes[0] == 1 && bytes[1..3] == SHA2_256 => topic_hash(topic) == hash,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Topic {
+        serde_json::from_str(include_str!("../sample_topics/sample.json"))
+            .expect("sample topic should read as a topic")
+    }
+
+    #[test]
+    fn v1_hash_is_pinned() {
+        let topic = sample();
+        let hash = "7CRkSJzpBTGnD49pJosUdu4ygpMJTNAfXG2SUa3CNce7ckd";
+
+        assert_eq!(topic_hash(&topic), hash);
+        assert!(verify(&topic, hash));
+    }
+
+    #[test]
+    fn hash_ignores_insertion_order() {
+        let mut a = Topic::new("t", "d");
+        let mut b = a.clone();
+        let (x, y) = (Uuid::new_v4(), Uuid::new_v4());
+        a.set_delegate(&x, "x");
+        a.set_delegate(&y, "y");
+        b.set_delegate(&y, "y");
+        b.set_delegate(&x, "x");
+
+        assert_eq!(topic_hash(&a), topic_hash(&b));
+    }
+
+    #[test]
+    fn policy_order_is_hashed_once_set() {
+        let mut topic = sample();
+        let before = topic_hash(&topic);
+
+        topic.policy_order = topic.ordered_policies();
+        topic.policy_order.reverse();
+        assert_ne!(topic_hash(&topic), before);
+        assert!(!verify(&topic, &before));
+    }
+
+    // the legacy value depends on how the `vote` crate writes `VoteData`, so
+    // this checks the shape and that it still verifies, not the value
+    #[test]
+    fn legacy_hashes_still_verify() {
+        let mut topic = sample();
+        let legacy = legacy_hash(&topic).expect("sample should convert to TopicData");
+
+        assert_eq!(decode(&legacy).into_vec().unwrap().len(), 32);
+        assert!(verify(&topic, &legacy));
+
+        topic.title = "changed".to_string();
+        assert!(!verify(&topic, &legacy));
+    }
+
+    #[test]
+    fn garbage_does_not_verify() {
+        assert!(!verify(&sample(), "not base58 at all!"));
+        assert!(!verify(&sample(), "3mJr7AoUXx2Wqd"));
+    }
+}
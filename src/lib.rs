@@ -0,0 +1,21 @@
+pub mod archive;
+pub mod calculation;
+pub mod chain;
+pub mod deadlines;
+pub mod diff;
+pub mod error;
+pub mod file_store;
+pub mod fsck;
+pub mod handlers;
+pub mod hashing;
+pub mod ipfs;
+pub mod memory_store;
+pub mod model;
+pub mod operations;
+pub mod redis_object;
+pub mod redis_store;
+pub mod redis_util;
+pub mod store;
+pub mod validation;
+
+pub use redis_object::RedisObject;
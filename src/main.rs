@@ -1,18 +1,10 @@
-mod handlers;
-mod ipfs;
-mod model;
-mod redis_object;
-mod redis_util;
-
+use actix::Actor;
 use actix_cors::Cors;
-use actix_redis::RedisActor;
 use actix_web::{middleware, web, App, HttpServer};
 use dotenv;
-use std::env;
-
-pub use redis_object::RedisObject;
+use dump::{deadlines::Deadlines, error, handlers::*, store};
 
-use handlers::*;
+const IMPORT_LIMIT: usize = 256 * 1024 * 1024;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -20,42 +12,76 @@ async fn main() -> std::io::Result<()> {
     std::env::set_var("RUST_LOG", "actix_web=trace,actix_redis=trace,dump=trace");
     env_logger::init();
 
-    HttpServer::new(|| {
-        let redis_addr: String = env::var("REDIS_ADDR").unwrap_or("127.0.0.1".to_string());
-
-        let redis_port: String = env::var("REDIS_PORT").unwrap_or("6379".to_string());
-
-        let address = format!("{}:{}", redis_addr, redis_port);
+    // the store is shared by all workers, so the in-memory backend sees the same data
+    // whichever worker picks up the request
+    let store = store::from_env(true);
 
-        let redis_addr = RedisActor::start(&address);
+    // opens and closes scheduled topics
+    Deadlines::new(store.clone()).start();
 
+    HttpServer::new(move || {
         // TODO: change this
         let cors = Cors::permissive();
 
         App::new()
-            .data(redis_addr)
+            .app_data(web::Data::from(store.clone()))
+            // malformed bodies, queries and paths get the same error envelope as
+            // everything else
+            .app_data(
+                web::JsonConfig::default().error_handler(|err, _req| {
+                    error::DumpError::Validation(err.to_string()).into()
+                }),
+            )
+            .app_data(
+                web::QueryConfig::default().error_handler(|err, _req| {
+                    error::DumpError::Validation(err.to_string()).into()
+                }),
+            )
+            .app_data(
+                web::PathConfig::default().error_handler(|err, _req| {
+                    error::DumpError::Validation(err.to_string()).into()
+                }),
+            )
             .wrap(middleware::Logger::default())
             .wrap(cors)
             .service(
                 web::scope("/db")
                     // hello/
                     .service(hello)
-                    // * list/
+                    // health/
+                    .service(health)
+                    // * list/ (?state=)
                     .service(get_list)
                     // * rep/ ({repid})
                     .service(get_reps)
                     .service(get_rep)
                     .service(post_rep)
                     .service(get_header)
-                    // * history/id/
+                    // * history/id/ (?offset=&limit=)
                     .service(history)
+                    // * history/id/count/
+                    .service(history_count)
                     // * result/hash/
                     .service(dump_result)
                     .service(get_result)
+                    // * result/topic/id/ (?at=)
+                    .service(get_result_by_id)
                     // * topic/update/id/delegate/
                     .service(update_vote)
+                    // * topic/update/id/delegate/voter/withdraw/ (?redistribute=)
+                    .service(withdraw_voter)
                     // * topic/update/id/policy/
                     .service(add_policy)
+                    // * topic/update/id/policy/policy/rename/
+                    .service(rename_policy)
+                    // * topic/update/id/policy/policy/remove/ (?redistribute=)
+                    .service(remove_policy)
+                    // * topic/update/id/policies/order/
+                    .service(reorder_policies)
+                    // * topic/update/id/aggregation/
+                    .service(set_aggregation)
+                    // * topic/update/id/batch/
+                    .service(batch_update)
                     // * topic/update/id/field/
                     .service(update_field)
                     // topic/new/
@@ -64,8 +90,35 @@ async fn main() -> std::io::Result<()> {
                     .service(get_topic_raw)
                     // * topic/raw/hash/
                     .service(post_topic_raw)
-                    // * topic/id/
-                    .service(get_topic_by_id),
+                    // * topic/state/id/
+                    .service(set_state)
+                    // * topic/schedule/id/
+                    .service(set_schedule)
+                    // * topic/revert/id/hash/
+                    .service(revert_topic)
+                    // * topic/fork/id/
+                    .service(fork_topic)
+                    // * topic/lineage/id/
+                    .service(lineage)
+                    // * topic/diff/a/b/
+                    .service(diff_topics)
+                    // * topic/chain/id/
+                    .service(validate_chain)
+                    // * topic/vote/id/ (?at=)
+                    .service(get_vote_by_id)
+                    // * topic/id/ (?at=)
+                    .service(get_topic_by_id)
+                    // * admin/export/
+                    .service(export)
+                    // * admin/import/
+                    .service(
+                        web::resource("/admin/import/")
+                            // archives are bigger than the default limit
+                            .app_data(web::PayloadConfig::new(IMPORT_LIMIT))
+                            .route(web::post().to(import)),
+                    )
+                    // * admin/fsck/
+                    .service(check),
             )
     })
     .bind("0.0.0.0:8082")?
@@ -0,0 +1,245 @@
+use crate::store::{Store, StoreError, Write};
+use async_trait::async_trait;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::{Mutex, MutexGuard},
+};
+
+/// Plain in-process copy of the Redis data types we use.
+#[derive(Debug, Default)]
+pub struct Keyspace {
+    pub(crate) strings: HashMap<String, Vec<u8>>,
+    pub(crate) sets: HashMap<String, BTreeSet<String>>,
+    pub(crate) lists: HashMap<String, Vec<String>>,
+    pub(crate) hashes: HashMap<String, BTreeMap<String, Vec<u8>>>,
+}
+
+impl Keyspace {
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.strings.get(key).cloned()
+    }
+
+    pub fn set(&mut self, key: &str, value: &[u8]) {
+        self.strings.insert(key.to_string(), value.to_vec());
+    }
+
+    pub fn set_add(&mut self, set: &str, member: &str) -> i64 {
+        let added = self
+            .sets
+            .entry(set.to_string())
+            .or_default()
+            .insert(member.to_string());
+        added as i64
+    }
+
+    pub fn set_members(&self, set: &str) -> Vec<String> {
+        self.sets
+            .get(set)
+            .map(|s| s.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn list_push(&mut self, list: &str, value: &str) -> i64 {
+        let list = self.lists.entry(list.to_string()).or_default();
+        list.push(value.to_string());
+        list.len() as i64
+    }
+
+    pub fn list_len(&self, list: &str) -> i64 {
+        self.lists.get(list).map_or(0, |l| l.len() as i64)
+    }
+
+    pub fn list_range(&self, list: &str, start: i64, stop: i64) -> Vec<String> {
+        let list = match self.lists.get(list) {
+            Some(l) => l,
+            None => return Vec::new(),
+        };
+
+        match range(list.len(), start, stop) {
+            Some((start, stop)) => list[start..=stop].to_vec(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn hash_set(&mut self, hash: &str, field: &str, value: &[u8]) -> i64 {
+        let previous = self
+            .hashes
+            .entry(hash.to_string())
+            .or_default()
+            .insert(field.to_string(), value.to_vec());
+        previous.is_none() as i64
+    }
+
+    pub fn hash_get(&self, hash: &str, field: &str) -> Option<Vec<u8>> {
+        self.hashes.get(hash).and_then(|h| h.get(field).cloned())
+    }
+
+    pub fn hash_keys(&self, hash: &str) -> Vec<String> {
+        self.hashes
+            .get(hash)
+            .map(|h| h.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn write(&mut self, write: &Write) {
+        match write {
+            Write::Set { key, value } => self.set(key, value),
+            Write::SetAdd { set, member } => {
+                self.set_add(set, member);
+            }
+            Write::ListPush { list, value } => {
+                self.list_push(list, value);
+            }
+            Write::HashSet { hash, field, value } => {
+                self.hash_set(hash, field, value);
+            }
+        }
+    }
+
+    /// the check half of `Store::transaction`
+    pub fn holds(&self, key: &str, expected: Option<&[u8]>) -> bool {
+        self.strings.get(key).map(|v| v.as_slice()) == expected
+    }
+}
+
+/// Resolves a LRANGE style (inclusive, possibly negative) range against a list
+/// of `len` elements. `None` means the range is empty.
+fn range(len: usize, start: i64, stop: i64) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 { len + start } else { start }.max(0);
+    let stop = if stop < 0 { len + stop } else { stop }.min(len - 1);
+
+    if start > stop || start >= len {
+        None
+    } else {
+        Some((start as usize, stop as usize))
+    }
+}
+
+/// Keeps everything in memory, for tests and running without a Redis server.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    keyspace: Mutex<Keyspace>,
+}
+
+impl MemoryStore {
+    fn lock(&self) -> Result<MutexGuard<'_, Keyspace>, StoreError> {
+        self.keyspace
+            .lock()
+            .map_err(|e| StoreError::Backend(e.to_string()))
+    }
+}
+
+#[async_trait(?Send)]
+impl Store for MemoryStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StoreError> {
+        Ok(self.lock()?.get(key))
+    }
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<(), StoreError> {
+        self.lock()?.set(key, value);
+        Ok(())
+    }
+
+    async fn set_add(&self, set: &str, member: &str) -> Result<i64, StoreError> {
+        Ok(self.lock()?.set_add(set, member))
+    }
+
+    async fn set_members(&self, set: &str) -> Result<Vec<String>, StoreError> {
+        Ok(self.lock()?.set_members(set))
+    }
+
+    async fn list_push(&self, list: &str, value: &str) -> Result<i64, StoreError> {
+        Ok(self.lock()?.list_push(list, value))
+    }
+
+    async fn list_range(
+        &self,
+        list: &str,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<String>, StoreError> {
+        Ok(self.lock()?.list_range(list, start, stop))
+    }
+
+    async fn list_len(&self, list: &str) -> Result<i64, StoreError> {
+        Ok(self.lock()?.list_len(list))
+    }
+
+    async fn hash_set(&self, hash: &str, field: &str, value: &[u8]) -> Result<i64, StoreError> {
+        Ok(self.lock()?.hash_set(hash, field, value))
+    }
+
+    async fn hash_get(&self, hash: &str, field: &str) -> Result<Option<Vec<u8>>, StoreError> {
+        Ok(self.lock()?.hash_get(hash, field))
+    }
+
+    async fn hash_keys(&self, hash: &str) -> Result<Vec<String>, StoreError> {
+        Ok(self.lock()?.hash_keys(hash))
+    }
+
+    async fn transaction(
+        &self,
+        watch: &str,
+        expected: Option<&[u8]>,
+        writes: Vec<Write>,
+    ) -> Result<bool, StoreError> {
+        let mut keyspace = self.lock()?;
+
+        if !keyspace.holds(watch, expected) {
+            return Ok(false);
+        }
+
+        writes.iter().for_each(|w| keyspace.write(w));
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_follows_lrange() {
+        // 0 1 2 3 4
+        assert_eq!(range(5, 0, -1), Some((0, 4)));
+        assert_eq!(range(5, 1, 2), Some((1, 2)));
+        assert_eq!(range(5, -2, -1), Some((3, 4)));
+        assert_eq!(range(5, -100, 1), Some((0, 1)));
+        assert_eq!(range(5, 3, 100), Some((3, 4)));
+        assert_eq!(range(5, 3, 1), None);
+        assert_eq!(range(5, 5, 10), None);
+        assert_eq!(range(5, -1, -2), None);
+        assert_eq!(range(0, 0, -1), None);
+    }
+
+    #[test]
+    fn list_range_on_missing_list_is_empty() {
+        assert!(Keyspace::default().list_range("nothing", 0, -1).is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn transaction_only_writes_over_what_it_watched() {
+        let store = MemoryStore::default();
+        let write = |value: &str| {
+            vec![Write::Set {
+                key: "key".to_string(),
+                value: value.as_bytes().to_vec(),
+            }]
+        };
+
+        assert!(store.transaction("key", None, write("a")).await.unwrap());
+        assert!(!store.transaction("key", None, write("b")).await.unwrap());
+        assert!(!store
+            .transaction("key", Some(b"b"), write("c"))
+            .await
+            .unwrap());
+        assert_eq!(store.get("key").await.unwrap(), Some(b"a".to_vec()));
+
+        assert!(store
+            .transaction("key", Some(b"a"), write("d"))
+            .await
+            .unwrap());
+        assert_eq!(store.get("key").await.unwrap(), Some(b"d".to_vec()));
+    }
+}
@@ -1,10 +1,318 @@
-use crate::RedisObject;
-use bs58::encode;
+use crate::{calculation, error::DumpError, hashing, RedisObject};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, convert::TryFrom};
 use uuid::Uuid;
 use vote::{TopicData, VoteData};
 
+/// A topic as we store it. It reads and writes the JSON of the `vote` crate's
+/// `TopicData` (see `sample_topics/sample.json`), plus what the server keeps
+/// about it on top, so a plain `TopicData` reads as a `Topic` with everything
+/// else at its default. The `vote` crate's own types are only ever reached
+/// through that JSON, see `to_data`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(try_from = "TopicJson")]
+pub struct Topic {
+    pub id: Uuid,
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(with = "pairs")]
+    pub policies: BTreeMap<Uuid, String>,
+    #[serde(with = "pairs")]
+    pub delegates: BTreeMap<Uuid, String>,
+    /// voter -> policy or delegate -> weight
+    #[serde(with = "votes")]
+    pub votes: BTreeMap<Uuid, BTreeMap<Uuid, f64>>,
+    /// display order of the policies, by id. Policies missing from it come
+    /// after the listed ones, by id. Empty until someone reorders them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub policy_order: Vec<Uuid>,
+    /// how results are computed, `Aggregation::default()` until someone sets it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aggregation: Option<Aggregation>,
+}
+
+/// What a `Topic` is read from: its own fields, or `method` and `params` at the
+/// top level instead of `aggregation`, the way `sample_topics/democracy.json`
+/// has them.
+#[derive(Deserialize)]
+struct TopicJson {
+    id: Uuid,
+    title: String,
+    #[serde(default)]
+    description: String,
+    #[serde(with = "pairs")]
+    policies: BTreeMap<Uuid, String>,
+    #[serde(with = "pairs")]
+    delegates: BTreeMap<Uuid, String>,
+    #[serde(with = "votes")]
+    votes: BTreeMap<Uuid, BTreeMap<Uuid, f64>>,
+    #[serde(default)]
+    policy_order: Vec<Uuid>,
+    #[serde(default)]
+    aggregation: Option<Aggregation>,
+    #[serde(default)]
+    method: Option<Method>,
+    #[serde(default)]
+    params: Option<Params>,
+}
+
+impl TryFrom<TopicJson> for Topic {
+    type Error = String;
+
+    fn try_from(json: TopicJson) -> Result<Self, Self::Error> {
+        let aggregation = match (json.aggregation, json.method, json.params) {
+            (aggregation, None, None) => aggregation,
+            (None, method, params) => Some(Aggregation {
+                method: method.unwrap_or_default(),
+                params: params.unwrap_or_default(),
+            }),
+            (Some(_), _, _) => {
+                return Err("give aggregation, or method and params, not both".to_string())
+            }
+        };
+
+        Ok(Self {
+            id: json.id,
+            title: json.title,
+            description: json.description,
+            policies: json.policies,
+            delegates: json.delegates,
+            votes: json.votes,
+            policy_order: json.policy_order,
+            aggregation,
+        })
+    }
+}
+
+impl Topic {
+    pub fn new(title: &str, description: &str) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            title: title.to_string(),
+            description: description.to_string(),
+            policies: BTreeMap::new(),
+            delegates: BTreeMap::new(),
+            votes: BTreeMap::new(),
+            policy_order: Vec::new(),
+            aggregation: None,
+        }
+    }
+
+    /// The topic as the `vote` crate's `TopicData`.
+    pub fn to_data(&self) -> Result<TopicData, DumpError> {
+        let value = serde_json::to_value(self)?;
+        serde_json::from_value(value).map_err(|e| DumpError::Internal(e.to_string()))
+    }
+
+    pub fn vote_data(&self) -> Result<VoteData, DumpError> {
+        Ok(self.to_data()?.into())
+    }
+
+    /// Adds the voter as a delegate, or renames them.
+    pub fn set_delegate(&mut self, id: &Uuid, name: &str) {
+        self.delegates.insert(*id, name.to_string());
+    }
+
+    /// Replaces the vote of `voter`.
+    pub fn set_vote(&mut self, voter: &Uuid, vote: BTreeMap<Uuid, f64>) {
+        self.votes.insert(*voter, vote);
+    }
+
+    /// Policy ids in display order.
+    pub fn ordered_policies(&self) -> Vec<Uuid> {
+        let mut ordered: Vec<Uuid> = self
+            .policy_order
+            .iter()
+            .filter(|id| self.policies.contains_key(id))
+            .cloned()
+            .collect();
+        for id in self.policies.keys() {
+            if !ordered.contains(id) {
+                ordered.push(*id);
+            }
+        }
+        ordered
+    }
+
+    /// Adds a policy, at the end of the display order.
+    pub fn add_policy(&mut self, policy: &str) -> Uuid {
+        let id = Uuid::new_v4();
+        self.policies.insert(id, policy.to_string());
+        if !self.policy_order.is_empty() {
+            self.policy_order.push(id);
+        }
+        id
+    }
+
+    /// `false` if there is no such policy.
+    pub fn rename_policy(&mut self, id: &Uuid, name: &str) -> bool {
+        match self.policies.get_mut(id) {
+            Some(policy) => {
+                *policy = name.to_string();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes a policy and the weights voters gave it, see `drop_target` for
+    /// `redistribute`. Returns the removed policy, `None` if there was no such
+    /// policy.
+    pub fn remove_policy(&mut self, id: &Uuid, redistribute: bool) -> Option<String> {
+        let removed = self.policies.remove(id)?;
+        self.policy_order.retain(|p| p != id);
+        self.drop_target(id, redistribute);
+
+        Some(removed)
+    }
+
+    /// Takes a voter out of the topic: out of the delegates, their own vote
+    /// gone, and out of every vote map that delegated to them, see
+    /// `drop_target` for `redistribute`. Returns the voter's name, `None` if
+    /// they weren't in the topic at all.
+    pub fn withdraw_voter(&mut self, id: &Uuid, redistribute: bool) -> Option<String> {
+        let name = self.delegates.remove(id);
+        let vote = self.votes.remove(id);
+        if name.is_none() && vote.is_none() {
+            return None;
+        }
+        self.drop_target(id, redistribute);
+
+        Some(name.unwrap_or_default())
+    }
+
+    /// Removes `target` (a policy or a delegate) from every vote map. With
+    /// `redistribute` the weight a voter gave it is spread over what else they
+    /// voted for, in proportion to the weights already there (evenly if those
+    /// are all zero). Otherwise it is simply gone.
+    fn drop_target(&mut self, target: &Uuid, redistribute: bool) {
+        for votes in self.votes.values_mut() {
+            let weight = match votes.remove(target) {
+                Some(weight) => weight,
+                None => continue,
+            };
+            if !redistribute || votes.is_empty() {
+                continue;
+            }
+
+            let rest: f64 = votes.values().sum();
+            let even = weight / votes.len() as f64;
+            for v in votes.values_mut() {
+                *v += if rest > 0.0 { weight * *v / rest } else { even };
+            }
+        }
+    }
+}
+
+/// `uuid -> value` maps as `[[uuid, value], ...]`, the way `TopicData` writes
+/// them. JSON objects keyed by uuid are read as well.
+mod pairs {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::BTreeMap;
+    use uuid::Uuid;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Either<V> {
+        Pairs(Vec<(Uuid, V)>),
+        Map(BTreeMap<Uuid, V>),
+    }
+
+    pub fn serialize<S: Serializer, V: Serialize>(
+        map: &BTreeMap<Uuid, V>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(map.iter())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, V: Deserialize<'de>>(
+        deserializer: D,
+    ) -> Result<BTreeMap<Uuid, V>, D::Error> {
+        Ok(match Either::deserialize(deserializer)? {
+            Either::Pairs(pairs) => pairs.into_iter().collect(),
+            Either::Map(map) => map,
+        })
+    }
+}
+
+/// Votes as a JSON object keyed by voter, each vote as `pairs`.
+mod votes {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::BTreeMap;
+    use uuid::Uuid;
+
+    type Votes = BTreeMap<Uuid, BTreeMap<Uuid, f64>>;
+
+    struct VoteRef<'a>(&'a BTreeMap<Uuid, f64>);
+
+    impl Serialize for VoteRef<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            super::pairs::serialize(self.0, serializer)
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct Vote(#[serde(with = "super::pairs")] BTreeMap<Uuid, f64>);
+
+    pub fn serialize<S: Serializer>(votes: &Votes, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(votes.iter().map(|(voter, vote)| (voter, VoteRef(vote))))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Votes, D::Error> {
+        let votes: BTreeMap<Uuid, Vote> = super::pairs::deserialize(deserializer)?;
+        Ok(votes
+            .into_iter()
+            .map(|(voter, vote)| (voter, vote.0))
+            .collect())
+    }
+}
+
+/// How the votes of a topic are turned into a result, so every client (and
+/// the server) computes it the same way.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub struct Aggregation {
+    #[serde(default)]
+    pub method: Method,
+    #[serde(default)]
+    pub params: Params,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Method {
+    /// delegations are followed until the weight lands on policies
+    Liquid,
+}
+
+impl Default for Method {
+    fn default() -> Self {
+        Method::Liquid
+    }
+}
+
+/// `null` (or missing) means the default of each.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub struct Params {
+    /// a weight counts as its square root, off by default
+    #[serde(default)]
+    pub is_quadratic: Option<bool>,
+    /// a voter's weights are scaled to add up to one, on by default
+    #[serde(default)]
+    pub is_normalize: Option<bool>,
+}
+
+impl Params {
+    pub fn quadratic(&self) -> bool {
+        self.is_quadratic.unwrap_or(false)
+    }
+
+    pub fn normalize(&self) -> bool {
+        self.is_normalize.unwrap_or(true)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct RepresentativeInfo {
     name: String,
@@ -17,22 +325,229 @@ pub struct TopicHeader {
     pub id: Uuid,
     pub hash: String,
     pub title: String,
+    /// hash of the `TopicVersion` for `hash`, missing on headers written before versions existed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// headers written before states existed are open
+    #[serde(default)]
+    pub state: State,
+    /// when the topic was closed, `hash` is its final version from then on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub closed_at: Option<DateTime<Utc>>,
+    /// a draft opens by itself at this time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opens_at: Option<DateTime<Utc>>,
+    /// voting ends at this time, the topic is closed and its result stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub closes_at: Option<DateTime<Utc>>,
 }
 
 impl TopicHeader {
-    pub fn new(id: &Uuid, hash: &str, title: &str) -> Self {
+    pub fn new(id: &Uuid, hash: &str, title: &str, version: Option<&str>) -> Self {
         Self {
             id: id.to_owned(),
             hash: hash.to_string(),
             title: title.to_string(),
+            version: version.map(|v| v.to_string()),
+            state: State::default(),
+            closed_at: None,
+            opens_at: None,
+            closes_at: None,
+        }
+    }
+
+    /// Whether the voting deadline has passed, even if nobody closed the topic yet.
+    pub fn past_deadline(&self) -> bool {
+        self.closes_at.map_or(false, |at| at <= Utc::now())
+    }
+}
+
+/// Where a topic is in its life. Only ever moves forward:
+/// draft -> open -> closed -> archived, or straight from draft to archived.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    /// being set up, policies can change but nobody votes yet
+    Draft,
+    Open,
+    /// no more changes, the current version is the final one
+    Closed,
+    /// closed and left out of the default `/list/`
+    Archived,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State::Open
+    }
+}
+
+impl State {
+    /// Whether a topic in this state takes an edit doing `operation`.
+    pub fn allows(&self, operation: Operation) -> bool {
+        match self {
+            State::Draft => operation != Operation::VoteOverwritten,
+            State::Open => true,
+            State::Closed | State::Archived => false,
+        }
+    }
+
+    pub fn can_become(&self, next: State) -> bool {
+        matches!(
+            (self, next),
+            (State::Draft, State::Open)
+                | (State::Draft, State::Archived)
+                | (State::Open, State::Closed)
+                | (State::Closed, State::Archived)
+        )
+    }
+}
+
+/// One link of a topic's version chain: which topic this version stored and
+/// the version it was made on top of, like a git commit.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TopicVersion {
+    /// hash of the `Topic`
+    pub topic: String,
+    /// hash of the previous `TopicVersion`, `None` for the first one
+    pub parent: Option<String>,
+}
+
+impl TopicVersion {
+    pub fn new(topic: &str, parent: Option<&str>) -> Self {
+        Self {
+            topic: topic.to_string(),
+            parent: parent.map(|p| p.to_string()),
         }
     }
 }
 
+/// Provenance of a forked topic, stored under `fork:{id}`. The forks of a
+/// topic are the `children:{parent}` set.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Fork {
+    /// the new topic
+    pub id: Uuid,
+    /// the topic it was copied from
+    pub parent: Uuid,
+    /// the version of `parent` that was copied
+    pub parent_hash: String,
+    pub at: DateTime<Utc>,
+}
+
+impl Fork {
+    pub fn new(id: &Uuid, parent: &Uuid, parent_hash: &str) -> Self {
+        Self {
+            id: id.to_owned(),
+            parent: parent.to_owned(),
+            parent_hash: parent_hash.to_string(),
+            at: Utc::now(),
+        }
+    }
+}
+
+/// What an edit did to a topic, recorded in its history.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Created,
+    /// the whole topic was posted through `topic/raw/`
+    Posted,
+    TitleChanged,
+    DescriptionChanged,
+    PolicyAdded,
+    PolicyRenamed,
+    PolicyRemoved,
+    PoliciesReordered,
+    /// the aggregation method or its params changed
+    AggregationChanged,
+    VoteOverwritten,
+    /// a voter left the topic
+    VoterWithdrawn,
+    /// several of the above at once, through `topic/update/{id}/batch/`
+    Batch,
+    /// an earlier version was made current again
+    Reverted,
+    /// first version of a topic copied from another one
+    Forked,
+}
+
+/// An edit about to be committed: what it does and who did it, if we know.
+#[derive(Debug, Clone, Copy)]
+pub struct Change {
+    pub operation: Operation,
+    pub actor: Option<Uuid>,
+    /// the voter the change was made to, when that isn't the actor
+    pub subject: Option<Uuid>,
+}
+
+impl Change {
+    pub fn new(operation: Operation, actor: Option<Uuid>) -> Self {
+        Self {
+            operation,
+            actor,
+            subject: None,
+        }
+    }
+
+    pub fn about(self, subject: Option<Uuid>) -> Self {
+        Self { subject, ..self }
+    }
+}
+
+/// One element of `history:{id}`.
+///
+/// Stored as JSON. Lists written before entries had metadata hold the bare
+/// topic hash instead, `parse` reads those as an entry with only `hash` set.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HistoryEntry {
+    pub hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operation: Option<Operation>,
+    /// the voter who made the change
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actor: Option<Uuid>,
+    /// the voter it was made to, e.g. the one withdrawn
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<Uuid>,
+}
+
+impl HistoryEntry {
+    /// An entry for `hash` committed right now.
+    pub fn new(hash: &str, change: &Change) -> Self {
+        Self {
+            hash: hash.to_string(),
+            at: Some(Utc::now()),
+            operation: Some(change.operation),
+            actor: change.actor,
+            subject: change.subject,
+        }
+    }
+
+    pub fn parse(raw: &str) -> Self {
+        serde_json::from_str(raw).unwrap_or_else(|_| Self {
+            hash: raw.to_string(),
+            at: None,
+            operation: None,
+            actor: None,
+            subject: None,
+        })
+    }
+
+    pub fn json(&self) -> String {
+        serde_json::to_string(self).expect("history entry should be serializable")
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TopicCalculationResult {
     pub topic_hash: String,
     data: serde_json::Value,
+    /// computed by the server when the topic closed, clients can't replace it
+    #[serde(default)]
+    pub computed: bool,
 }
 
 impl TopicCalculationResult {
@@ -40,6 +555,16 @@ impl TopicCalculationResult {
         Self {
             topic_hash: topic_hash.to_string(),
             data: data.to_owned(),
+            computed: false,
+        }
+    }
+
+    /// The result of `topic`, the version stored under `topic_hash`.
+    pub fn compute(topic_hash: &str, topic: &Topic) -> Self {
+        Self {
+            topic_hash: topic_hash.to_string(),
+            data: calculation::liquid(topic),
+            computed: true,
         }
     }
 }
@@ -64,31 +589,204 @@ impl RedisObject for TopicHeader {
     }
 }
 
-impl RedisObject for TopicData {
+impl RedisObject for Fork {
+    fn domain_prefix() -> String {
+        "fork".to_string()
+    }
+
+    fn hash(&self) -> String {
+        self.id.to_string()
+    }
+}
+
+impl RedisObject for TopicVersion {
+    fn domain_prefix() -> String {
+        "version".to_string()
+    }
+
+    fn hash(&self) -> String {
+        hashing::version_hash(self)
+    }
+}
+
+impl RedisObject for Topic {
     fn domain_prefix() -> String {
         "topic".to_string()
     }
 
+    /// see `hashing` for what goes into it
     fn hash(&self) -> String {
-        let mut hash = Sha256::new();
-        hash.update(self.title.as_bytes());
-        hash.update(self.description.as_bytes());
-
-        // FIXME: This is ugly. we are hasing the key and value of policies and delegates
-        // separately...
-        let p_values = self
-            .policies_values()
-            .iter()
-            .fold("".to_string(), |acc, d| format!("{}{}", acc, d));
-        hash.update(p_values.as_bytes());
-        let d_values = self
-            .delegates_values()
-            .iter()
-            .fold("".to_string(), |acc, d| format!("{}{}", acc, d));
-        hash.update(d_values.as_bytes());
-        let vote: VoteData = self.to_owned().into();
-        hash.update(&serde_json::to_vec(&vote).expect("VoteData should be Serializeable."));
+        hashing::topic_hash(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_and_writes_the_topic_data_format() {
+        let raw = include_str!("../sample_topics/sample.json");
+        let topic: Topic = serde_json::from_str(raw).unwrap();
+        assert_eq!(topic.policies.len(), 3);
+        assert_eq!(topic.delegates.len(), 3);
+        assert_eq!(topic.votes.len(), 3);
+
+        let written = serde_json::to_value(&topic).unwrap();
+        assert!(written["policies"][0].is_array());
+        assert!(written["votes"].is_object());
+        assert!(written.get("policy_order").is_none());
+        assert!(written.get("aggregation").is_none());
+
+        let again: Topic = serde_json::from_value(written).unwrap();
+        assert_eq!(again.votes, topic.votes);
+    }
+
+    #[test]
+    fn reads_maps_too() {
+        let (policy, voter) = (Uuid::new_v4(), Uuid::new_v4());
+        let raw = serde_json::json!({
+            "id": Uuid::new_v4(),
+            "title": "t",
+            "description": "d",
+            "policies": {policy.to_string(): "p"},
+            "delegates": {voter.to_string(): "v"},
+            "votes": {voter.to_string(): {policy.to_string(): 1.0}},
+        });
+        let topic: Topic = serde_json::from_value(raw).unwrap();
+
+        assert_eq!(topic.votes[&voter][&policy], 1.0);
+    }
+
+    #[test]
+    fn reads_method_and_params_at_the_top_level() {
+        let mut raw = serde_json::json!({
+            "id": Uuid::new_v4(),
+            "title": "t",
+            "policies": [],
+            "delegates": [],
+            "votes": {},
+            "method": "liquid",
+            "params": {"is_quadratic": true, "is_normalize": null},
+        });
+        let topic: Topic = serde_json::from_value(raw.clone()).unwrap();
+
+        let params = topic.aggregation.unwrap().params;
+        assert!(params.quadratic());
+        assert!(params.normalize());
+        assert!(serde_json::to_value(&topic)
+            .unwrap()
+            .get("method")
+            .is_none());
+
+        raw["aggregation"] = serde_json::json!({"method": "liquid"});
+        assert!(serde_json::from_value::<Topic>(raw).is_err());
+    }
+
+    #[test]
+    fn converts_to_topic_data() {
+        let mut topic = Topic::new("t", "d");
+        let policy = topic.add_policy("p");
+        let voter = Uuid::new_v4();
+        topic.set_delegate(&voter, "v");
+        topic.set_vote(&voter, vec![(policy, 1.0)].into_iter().collect());
+
+        let data = topic.to_data().unwrap();
+        assert_eq!(data.policies_values(), vec!["p".to_string()]);
+        assert_eq!(data.delegates_values(), vec!["v".to_string()]);
+        assert!(topic.vote_data().is_ok());
+    }
+
+    fn voted() -> (Topic, Uuid, Uuid, Uuid, Uuid) {
+        let mut topic = Topic::new("t", "d");
+        let bread = topic.add_policy("bread");
+        let rice = topic.add_policy("rice");
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        topic.set_delegate(&a, "a");
+        topic.set_delegate(&b, "b");
+        topic.set_vote(&a, vec![(bread, 3.0), (rice, 1.0)].into_iter().collect());
+        topic.set_vote(&b, vec![(a, 1.0), (rice, 1.0)].into_iter().collect());
+        (topic, bread, rice, a, b)
+    }
+
+    #[test]
+    fn removed_policy_weight_is_dropped() {
+        let (mut topic, bread, rice, a, _) = voted();
+
+        assert_eq!(topic.remove_policy(&bread, false).as_deref(), Some("bread"));
+        assert_eq!(topic.votes[&a], vec![(rice, 1.0)].into_iter().collect());
+        assert!(topic.remove_policy(&bread, false).is_none());
+    }
+
+    #[test]
+    fn removed_policy_weight_is_redistributed() {
+        let (mut topic, bread, rice, a, b) = voted();
+
+        topic.remove_policy(&rice, true);
+        assert_eq!(topic.votes[&a], vec![(bread, 4.0)].into_iter().collect());
+        assert_eq!(topic.votes[&b], vec![(a, 2.0)].into_iter().collect());
+    }
+
+    #[test]
+    fn redistribution_is_even_over_zero_weights() {
+        let (mut topic, bread, rice, a, _) = voted();
+        let noodles = topic.add_policy("noodles");
+        topic.set_vote(
+            &a,
+            vec![(bread, 0.0), (noodles, 0.0), (rice, 1.0)]
+                .into_iter()
+                .collect(),
+        );
+
+        topic.remove_policy(&rice, true);
+        assert_eq!(
+            topic.votes[&a],
+            vec![(bread, 0.5), (noodles, 0.5)].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn withdrawn_voter_is_gone_everywhere() {
+        let (mut topic, _, rice, a, b) = voted();
+
+        assert_eq!(topic.withdraw_voter(&a, true).as_deref(), Some("a"));
+        assert!(!topic.delegates.contains_key(&a));
+        assert!(!topic.votes.contains_key(&a));
+        assert_eq!(topic.votes[&b], vec![(rice, 2.0)].into_iter().collect());
+        assert!(topic.withdraw_voter(&a, true).is_none());
+    }
+
+    #[test]
+    fn new_policies_go_last_once_ordered() {
+        let (mut topic, bread, rice, _, _) = voted();
+        topic.policy_order = vec![rice, bread];
+        let noodles = topic.add_policy("noodles");
+
+        assert_eq!(topic.ordered_policies(), vec![rice, bread, noodles]);
+    }
+
+    #[test]
+    fn states_move_forward_only() {
+        assert!(State::Draft.can_become(State::Open));
+        assert!(State::Open.can_become(State::Closed));
+        assert!(State::Closed.can_become(State::Archived));
+        assert!(!State::Closed.can_become(State::Open));
+        assert!(!State::Archived.can_become(State::Draft));
+
+        assert!(!State::Draft.allows(Operation::VoteOverwritten));
+        assert!(State::Draft.allows(Operation::PolicyAdded));
+        assert!(!State::Closed.allows(Operation::TitleChanged));
+    }
+
+    #[test]
+    fn history_entries_read_bare_hashes() {
+        let entry = HistoryEntry::parse("somehash");
+        assert_eq!(entry.hash, "somehash");
+        assert!(entry.operation.is_none());
 
-        encode(hash.finalize()).into_string()
+        let change = Change::new(Operation::VoterWithdrawn, None).about(Some(Uuid::nil()));
+        let entry = HistoryEntry::parse(&HistoryEntry::new("h", &change).json());
+        assert_eq!(entry.hash, "h");
+        assert_eq!(entry.subject, Some(Uuid::nil()));
     }
 }
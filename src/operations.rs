@@ -0,0 +1,306 @@
+//! Edits that can be made to a topic, one at a time through their own
+//! endpoints or several at once through the batch endpoint.
+
+use crate::{
+    error::{DumpError, FieldError},
+    model::{Aggregation, Operation, Topic},
+    validation,
+};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Op {
+    SetTitle {
+        title: String,
+    },
+    SetDescription {
+        description: String,
+    },
+    AddPolicy {
+        policy: String,
+    },
+    RenamePolicy {
+        policy: Uuid,
+        name: String,
+    },
+    /// `redistribute` as in `Topic::remove_policy`
+    RemovePolicy {
+        policy: Uuid,
+        #[serde(default)]
+        redistribute: bool,
+    },
+    /// every policy id of the topic, in the new display order
+    ReorderPolicies {
+        order: Vec<Uuid>,
+    },
+    /// `null` goes back to the default
+    SetAggregation {
+        aggregation: Option<Aggregation>,
+    },
+    /// adds the voter as a delegate (or renames them) and replaces their vote
+    SetVote {
+        id: Uuid,
+        name: String,
+        vote: BTreeMap<Uuid, f64>,
+    },
+    /// `redistribute` as in `Topic::withdraw_voter`
+    RemoveVoter {
+        voter: Uuid,
+        #[serde(default)]
+        redistribute: bool,
+    },
+}
+
+impl Op {
+    /// what goes into the history when this is the only edit
+    pub fn operation(&self) -> Operation {
+        match self {
+            Op::SetTitle { .. } => Operation::TitleChanged,
+            Op::SetDescription { .. } => Operation::DescriptionChanged,
+            Op::AddPolicy { .. } => Operation::PolicyAdded,
+            Op::RenamePolicy { .. } => Operation::PolicyRenamed,
+            Op::RemovePolicy { .. } => Operation::PolicyRemoved,
+            Op::ReorderPolicies { .. } => Operation::PoliciesReordered,
+            Op::SetAggregation { .. } => Operation::AggregationChanged,
+            Op::SetVote { .. } => Operation::VoteOverwritten,
+            Op::RemoveVoter { .. } => Operation::VoterWithdrawn,
+        }
+    }
+
+    /// the voter this op is done to, if it isn't necessarily who sends it
+    pub fn subject(&self) -> Option<Uuid> {
+        match self {
+            Op::RemoveVoter { voter, .. } => Some(*voter),
+            _ => None,
+        }
+    }
+}
+
+/// Applies `op` to `topic`. Returns the id of the new policy for `AddPolicy`.
+/// On error `topic` is left as it was.
+pub fn apply(topic: &mut Topic, op: &Op) -> Result<Option<Uuid>, DumpError> {
+    match op {
+        Op::SetTitle { title } => topic.title = title.to_string(),
+        Op::SetDescription { description } => topic.description = description.to_string(),
+        Op::AddPolicy { policy } => return Ok(Some(topic.add_policy(policy))),
+        Op::RenamePolicy { policy, name } => {
+            if !topic.rename_policy(policy, name) {
+                return Err(DumpError::NotFound(format!("policy {}", policy)));
+            }
+        }
+        Op::RemovePolicy {
+            policy,
+            redistribute,
+        } => {
+            if topic.remove_policy(policy, *redistribute).is_none() {
+                return Err(DumpError::NotFound(format!("policy {}", policy)));
+            }
+        }
+        Op::ReorderPolicies { order } => {
+            let mut given = order.clone();
+            given.sort();
+            given.dedup();
+            if given.len() != order.len() || !given.iter().eq(topic.policies.keys()) {
+                return Err(DumpError::Validation(
+                    "order should list every policy of the topic exactly once".to_string(),
+                ));
+            }
+            topic.policy_order = order.clone();
+        }
+        Op::SetAggregation { aggregation } => topic.aggregation = *aggregation,
+        Op::SetVote { id, name, vote } => {
+            validation::ok(validation::check_vote(topic, id, vote, "vote"))?;
+            // we want to update the delegates info for what ever reason (name change);
+            topic.set_delegate(id, name);
+            topic.set_vote(id, vote.clone());
+        }
+        Op::RemoveVoter {
+            voter,
+            redistribute,
+        } => {
+            if topic.withdraw_voter(voter, *redistribute).is_none() {
+                return Err(DumpError::NotFound(format!("voter {}", voter)));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Applies every op in order, all or nothing. Returns the ids of the policies
+/// added, in order. The error says which op failed.
+pub fn apply_all(topic: &mut Topic, ops: &[Op]) -> Result<Vec<Uuid>, DumpError> {
+    let mut edited = topic.clone();
+    let mut added = Vec::new();
+
+    for (n, op) in ops.iter().enumerate() {
+        match apply(&mut edited, op) {
+            Ok(Some(id)) => added.push(id),
+            Ok(None) => {}
+            Err(DumpError::Fields(fields)) => {
+                let fields = fields
+                    .into_iter()
+                    .map(|e| FieldError {
+                        field: format!("{}.{}", n, e.field),
+                        mes: e.mes,
+                    })
+                    .collect();
+                return Err(DumpError::Fields(fields));
+            }
+            Err(e) => return Err(DumpError::Validation(format!("operation {}: {}", n, e))),
+        }
+    }
+
+    *topic = edited;
+    Ok(added)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn topic() -> (Topic, Uuid, Uuid) {
+        let mut topic = Topic::new("t", "d");
+        let bread = topic.add_policy("bread");
+        let rice = topic.add_policy("rice");
+        (topic, bread, rice)
+    }
+
+    #[test]
+    fn add_policy_returns_its_id() {
+        let (mut topic, _, _) = topic();
+        let op = Op::AddPolicy {
+            policy: "noodles".to_string(),
+        };
+
+        let id = apply(&mut topic, &op).unwrap().unwrap();
+        assert_eq!(topic.policies[&id], "noodles");
+    }
+
+    #[test]
+    fn reorder_needs_every_policy_once() {
+        let (mut topic, bread, rice) = topic();
+
+        for order in [
+            vec![bread],
+            vec![bread, bread],
+            vec![bread, rice, Uuid::new_v4()],
+        ] {
+            let op = Op::ReorderPolicies { order };
+            assert!(matches!(
+                apply(&mut topic, &op),
+                Err(DumpError::Validation(_))
+            ));
+        }
+        assert!(topic.policy_order.is_empty());
+
+        let op = Op::ReorderPolicies {
+            order: vec![rice, bread],
+        };
+        apply(&mut topic, &op).unwrap();
+        assert_eq!(topic.ordered_policies(), vec![rice, bread]);
+    }
+
+    #[test]
+    fn unknown_targets_are_not_found() {
+        let (mut topic, _, _) = topic();
+        let missing = Uuid::new_v4();
+
+        let ops = vec![
+            Op::RenamePolicy {
+                policy: missing,
+                name: "x".to_string(),
+            },
+            Op::RemovePolicy {
+                policy: missing,
+                redistribute: false,
+            },
+            Op::RemoveVoter {
+                voter: missing,
+                redistribute: false,
+            },
+        ];
+        for op in ops {
+            assert!(matches!(
+                apply(&mut topic, &op),
+                Err(DumpError::NotFound(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn set_vote_is_validated() {
+        let (mut topic, bread, _) = topic();
+        let voter = Uuid::new_v4();
+        let op = Op::SetVote {
+            id: voter,
+            name: "v".to_string(),
+            vote: vec![(bread, -1.0)].into_iter().collect(),
+        };
+
+        assert!(matches!(apply(&mut topic, &op), Err(DumpError::Fields(_))));
+        assert!(topic.votes.is_empty());
+        assert!(topic.delegates.is_empty());
+    }
+
+    #[test]
+    fn apply_all_is_all_or_nothing() {
+        let (mut topic, bread, _) = topic();
+        let before = topic.clone();
+        let ops = vec![
+            Op::SetTitle {
+                title: "new".to_string(),
+            },
+            Op::RemovePolicy {
+                policy: Uuid::new_v4(),
+                redistribute: false,
+            },
+        ];
+
+        let err = apply_all(&mut topic, &ops).unwrap_err();
+        assert!(err.to_string().contains("operation 1"));
+        assert_eq!(topic.title, before.title);
+
+        let ops = vec![
+            Op::SetTitle {
+                title: "new".to_string(),
+            },
+            Op::AddPolicy {
+                policy: "noodles".to_string(),
+            },
+            Op::RemovePolicy {
+                policy: bread,
+                redistribute: false,
+            },
+        ];
+        let added = apply_all(&mut topic, &ops).unwrap();
+        assert_eq!(added.len(), 1);
+        assert_eq!(topic.title, "new");
+        assert!(!topic.policies.contains_key(&bread));
+    }
+
+    #[test]
+    fn apply_all_prefixes_field_errors() {
+        let (mut topic, bread, _) = topic();
+        let ops = vec![
+            Op::SetTitle {
+                title: "new".to_string(),
+            },
+            Op::SetVote {
+                id: Uuid::new_v4(),
+                name: "v".to_string(),
+                vote: vec![(bread, f64::NAN)].into_iter().collect(),
+            },
+        ];
+
+        match apply_all(&mut topic, &ops) {
+            Err(DumpError::Fields(fields)) => {
+                assert_eq!(fields[0].field, format!("1.vote.{}", bread))
+            }
+            other => panic!("expected field errors, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,345 @@
+use crate::store::{Store, StoreError, Write};
+use actix::{clock::delay_for, Addr};
+use actix_redis::{Command, RedisActor};
+use actix_web::rt::time::timeout;
+use async_trait::async_trait;
+use redis_async::{
+    client::{paired_connect, PairedConnection},
+    resp::RespValue as Value,
+    resp_array,
+};
+use std::{env, future::Future, net::ToSocketAddrs, sync::Mutex, time::Duration};
+
+#[derive(Debug, Clone)]
+pub struct RedisConfig {
+    pub address: String,
+    /// how many idle transaction connections to keep around
+    pub pool_size: usize,
+    /// how long a command may take before we call redis unreachable
+    pub timeout: Duration,
+    /// how often the health monitor pings redis while it is up
+    pub health_interval: Duration,
+    /// the longest the health monitor waits between pings while redis is down
+    pub max_backoff: Duration,
+}
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+impl RedisConfig {
+    /// `REDIS_ADDR`, `REDIS_PORT`, `REDIS_POOL_SIZE`, and `REDIS_TIMEOUT`,
+    /// `REDIS_HEALTH_INTERVAL`, `REDIS_MAX_BACKOFF` in seconds.
+    pub fn from_env() -> Self {
+        let redis_addr: String = env::var("REDIS_ADDR").unwrap_or("127.0.0.1".to_string());
+        let redis_port: String = env::var("REDIS_PORT").unwrap_or("6379".to_string());
+
+        Self {
+            address: format!("{}:{}", redis_addr, redis_port),
+            pool_size: env_or("REDIS_POOL_SIZE", 4),
+            timeout: Duration::from_secs(env_or("REDIS_TIMEOUT", 5)),
+            health_interval: Duration::from_secs(env_or("REDIS_HEALTH_INTERVAL", 10)),
+            max_backoff: Duration::from_secs(env_or("REDIS_MAX_BACKOFF", 60)),
+        }
+    }
+}
+
+/// One shared connection manager for the whole server.
+///
+/// Plain commands are multiplexed over a single `RedisActor`, which reconnects
+/// with an exponential backoff by itself whenever the connection drops.
+/// Transactions need a connection of their own, those are pooled here.
+pub struct RedisStore {
+    redis: Addr<RedisActor>,
+    config: RedisConfig,
+    pool: Mutex<Vec<PairedConnection>>,
+}
+
+impl RedisStore {
+    pub fn start(config: RedisConfig) -> Self {
+        let redis = RedisActor::start(&config.address);
+
+        actix::spawn(monitor(redis.clone(), config.clone()));
+
+        Self {
+            redis,
+            config,
+            pool: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The actor multiplexes every request over one connection, so a WATCH sent
+    /// through it would be shared with whatever else is in flight. Transactions
+    /// get a connection of their own instead.
+    ///
+    /// Pooled connections may have gone stale while idle (redis restarted, or
+    /// dropped idle clients), each one is pinged before use and dropped if it
+    /// doesn't answer.
+    async fn dedicated_connection(&self) -> Result<PairedConnection, StoreError> {
+        while let Some(connection) = self.pooled() {
+            match send_on(&connection, resp_array!["PING"], self.config.timeout).await {
+                Ok(Value::SimpleString(_)) => return Ok(connection),
+                ping => log::info!("dropping a stale redis connection: {:?}", ping),
+            }
+        }
+
+        let address = self
+            .config
+            .address
+            .to_socket_addrs()
+            .map_err(|e| StoreError::Unavailable(e.to_string()))?
+            .next()
+            .ok_or_else(|| {
+                StoreError::Unavailable(format!("could not resolve {}", self.config.address))
+            })?;
+
+        within(self.config.timeout, async {
+            paired_connect(&address)
+                .await
+                .map_err(|e| StoreError::Unavailable(e.to_string()))
+        })
+        .await
+    }
+
+    /// An idle connection, the pool isn't kept locked while it is used.
+    fn pooled(&self) -> Option<PairedConnection> {
+        self.pool.lock().ok().and_then(|mut pool| pool.pop())
+    }
+
+    /// Hands a connection back after a transaction that left it in a clean state.
+    fn release(&self, connection: PairedConnection) {
+        if let Ok(mut pool) = self.pool.lock() {
+            if pool.len() < self.config.pool_size {
+                pool.push(connection);
+            }
+        }
+    }
+
+    async fn send(&self, command: Value) -> Result<Value, StoreError> {
+        match self
+            .redis
+            .send(Command(command))
+            .timeout(self.config.timeout)
+            .await
+        {
+            Ok(Ok(Value::Error(e))) => Err(StoreError::Backend(e)),
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(e)) => Err(StoreError::Unavailable(e.to_string())),
+            Err(e) => Err(StoreError::Unavailable(e.to_string())),
+        }
+    }
+}
+
+/// Pings redis in the background so an outage shows up in the logs, and not
+/// only as failing requests. While redis is down the pings back off.
+async fn monitor(redis: Addr<RedisActor>, config: RedisConfig) {
+    let mut wait = config.health_interval;
+    let mut healthy = true;
+
+    loop {
+        delay_for(wait).await;
+
+        let ping = redis
+            .send(Command(resp_array!["PING"]))
+            .timeout(config.timeout)
+            .await;
+        let up = matches!(ping, Ok(Ok(Value::SimpleString(_))));
+
+        match (healthy, up) {
+            (true, false) => log::error!("lost redis at {}: {:?}", config.address, ping),
+            (false, true) => log::info!("redis at {} is back", config.address),
+            (false, false) => log::warn!("redis at {} is still unreachable", config.address),
+            (true, true) => {}
+        }
+        healthy = up;
+
+        wait = if up {
+            config.health_interval
+        } else {
+            (wait * 2).min(config.max_backoff)
+        };
+    }
+}
+
+/// `future`, unless redis takes longer than `limit`.
+async fn within<T>(
+    limit: Duration,
+    future: impl Future<Output = Result<T, StoreError>>,
+) -> Result<T, StoreError> {
+    timeout(limit, future)
+        .await
+        .map_err(|_| StoreError::Unavailable(format!("redis did not answer within {:?}", limit)))?
+}
+
+async fn send_on(
+    connection: &PairedConnection,
+    command: Value,
+    limit: Duration,
+) -> Result<Value, StoreError> {
+    let reply = within(limit, async {
+        connection
+            .send::<Value>(command)
+            .await
+            .map_err(|e| StoreError::Unavailable(e.to_string()))
+    })
+    .await?;
+
+    match reply {
+        Value::Error(e) => Err(StoreError::Backend(e)),
+        value => Ok(value),
+    }
+}
+
+fn command(write: Write) -> Value {
+    match write {
+        Write::Set { key, value } => resp_array!["SET", key, value],
+        Write::SetAdd { set, member } => resp_array!["SADD", set, member],
+        Write::ListPush { list, value } => resp_array!["RPUSH", list, value],
+        Write::HashSet { hash, field, value } => resp_array!["HSET", hash, field, value],
+    }
+}
+
+fn unexpected(command: &str, value: Value) -> StoreError {
+    StoreError::Backend(format!("unexpected reply to {}: {:?}", command, value))
+}
+
+fn integer(command: &str, value: Value) -> Result<i64, StoreError> {
+    match value {
+        Value::Integer(x) => Ok(x),
+        v => Err(unexpected(command, v)),
+    }
+}
+
+fn bulk(command: &str, value: Value) -> Result<Option<Vec<u8>>, StoreError> {
+    match value {
+        Value::BulkString(x) => Ok(Some(x)),
+        Value::Nil => Ok(None),
+        v => Err(unexpected(command, v)),
+    }
+}
+
+fn strings(command: &str, value: Value) -> Result<Vec<String>, StoreError> {
+    match value {
+        Value::Array(values) => Ok(values
+            .into_iter()
+            .filter_map(|v| match v {
+                Value::BulkString(x) => String::from_utf8(x).ok(),
+                _ => None,
+            })
+            .collect()),
+        v => Err(unexpected(command, v)),
+    }
+}
+
+#[async_trait(?Send)]
+impl Store for RedisStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StoreError> {
+        bulk("GET", self.send(resp_array!["GET", key]).await?)
+    }
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<(), StoreError> {
+        match self.send(resp_array!["SET", key, value]).await? {
+            Value::SimpleString(x) if x == "OK" => Ok(()),
+            v => Err(unexpected("SET", v)),
+        }
+    }
+
+    async fn set_add(&self, set: &str, member: &str) -> Result<i64, StoreError> {
+        integer("SADD", self.send(resp_array!["SADD", set, member]).await?)
+    }
+
+    async fn set_members(&self, set: &str) -> Result<Vec<String>, StoreError> {
+        strings("SMEMBERS", self.send(resp_array!["SMEMBERS", set]).await?)
+    }
+
+    async fn list_push(&self, list: &str, value: &str) -> Result<i64, StoreError> {
+        integer("RPUSH", self.send(resp_array!["RPUSH", list, value]).await?)
+    }
+
+    async fn list_range(
+        &self,
+        list: &str,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<String>, StoreError> {
+        let command = resp_array!["LRANGE", list, start.to_string(), stop.to_string()];
+        strings("LRANGE", self.send(command).await?)
+    }
+
+    async fn list_len(&self, list: &str) -> Result<i64, StoreError> {
+        integer("LLEN", self.send(resp_array!["LLEN", list]).await?)
+    }
+
+    async fn hash_set(&self, hash: &str, field: &str, value: &[u8]) -> Result<i64, StoreError> {
+        integer(
+            "HSET",
+            self.send(resp_array!["HSET", hash, field, value]).await?,
+        )
+    }
+
+    async fn hash_get(&self, hash: &str, field: &str) -> Result<Option<Vec<u8>>, StoreError> {
+        bulk("HGET", self.send(resp_array!["HGET", hash, field]).await?)
+    }
+
+    async fn hash_keys(&self, hash: &str) -> Result<Vec<String>, StoreError> {
+        strings("HKEYS", self.send(resp_array!["HKEYS", hash]).await?)
+    }
+
+    async fn transaction(
+        &self,
+        watch: &str,
+        expected: Option<&[u8]>,
+        writes: Vec<Write>,
+    ) -> Result<bool, StoreError> {
+        let connection = self.dedicated_connection().await?;
+
+        // a connection that failed half way might still be in a MULTI, so only
+        // connections that made it through go back to the pool
+        let committed =
+            transaction(&connection, watch, expected, writes, self.config.timeout).await?;
+        self.release(connection);
+
+        Ok(committed)
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        match self.send(resp_array!["PING"]).await? {
+            Value::SimpleString(_) => Ok(()),
+            v => Err(unexpected("PING", v)),
+        }
+    }
+}
+
+async fn transaction(
+    connection: &PairedConnection,
+    watch: &str,
+    expected: Option<&[u8]>,
+    writes: Vec<Write>,
+    limit: Duration,
+) -> Result<bool, StoreError> {
+    send_on(connection, resp_array!["WATCH", watch], limit).await?;
+    let current = bulk(
+        "GET",
+        send_on(connection, resp_array!["GET", watch], limit).await?,
+    )?;
+
+    if current.as_deref() != expected {
+        send_on(connection, resp_array!["UNWATCH"], limit).await?;
+        return Ok(false);
+    }
+
+    send_on(connection, resp_array!["MULTI"], limit).await?;
+    for write in writes {
+        send_on(connection, command(write), limit).await?;
+    }
+
+    match send_on(connection, resp_array!["EXEC"], limit).await? {
+        // the watched key was touched in the meantime and EXEC was aborted
+        Value::Nil => Ok(false),
+        Value::Array(_) => Ok(true),
+        v => Err(unexpected("EXEC", v)),
+    }
+}
@@ -1,153 +1,409 @@
-use crate::{model::RepresentativeInfo, RedisObject};
-use actix::Addr;
-use actix_redis::{Command, RedisActor};
-use actix_web::{web, Error as AWError, HttpResponse};
-use futures::future::{join, join_all};
-use redis_async::{resp::RespValue as Value, resp_array};
-use serde_json::json;
-use uuid::Uuid;
-
-// TODO this is obscuring the error, not best practice
-pub async fn add(obj: &impl RedisObject, redis: &web::Data<Addr<RedisActor>>) -> Option<String> {
+use crate::{
+    error::DumpError,
+    model::{
+        Change, Fork, HistoryEntry, RepresentativeInfo, State, Topic, TopicCalculationResult,
+        TopicHeader, TopicVersion,
+    },
+    store::{Store, Write},
+    RedisObject,
+};
+use chrono::{DateTime, Utc};
+use futures::future::join;
+use serde::de::DeserializeOwned;
+use std::convert::TryFrom;
+
+pub async fn add(obj: &impl RedisObject, store: &dyn Store) -> Result<String, DumpError> {
     let hash = obj.hash();
-    let add = redis.send(Command(resp_array!["SET", obj.domain(), obj.json()]));
-    let list = redis.send(Command(resp_array!["SADD", &obj.plural_prefix(), &hash]));
-    let (add, _list) = join(add, list).await;
-    if let Ok(Ok(Value::SimpleString(x))) = add {
-        if x == "OK" {
-            Some(hash)
-        } else {
-            None
+    let (domain, json, plural) = (obj.domain(), obj.json(), obj.plural_prefix());
+    let add = store.set(&domain, json.as_bytes());
+    let list = store.set_add(&plural, &hash);
+    let (add, list) = join(add, list).await;
+    add?;
+    list?;
+    Ok(hash)
+}
+
+/// Stores a result posted by a client, unless the server computed one for that
+/// version when its topic closed.
+pub async fn add_result(
+    result: &TopicCalculationResult,
+    store: &dyn Store,
+) -> Result<String, DumpError> {
+    let domain = result.domain();
+
+    loop {
+        let previous = store.get(&domain).await?;
+        if let Some(previous) = &previous {
+            if serde_json::from_slice::<TopicCalculationResult>(previous)?.computed {
+                return Err(DumpError::State(format!(
+                    "the result of {} was computed when its topic closed",
+                    result.topic_hash
+                )));
+            }
+        }
+
+        // the topic may close meanwhile
+        if store
+            .transaction(&domain, previous.as_deref(), writes(result))
+            .await?
+        {
+            return Ok(result.hash());
         }
-    } else {
-        None
     }
 }
 
+fn writes(obj: &impl RedisObject) -> Vec<Write> {
+    vec![
+        Write::Set {
+            key: obj.domain(),
+            value: obj.json().into_bytes(),
+        },
+        Write::SetAdd {
+            set: obj.plural_prefix(),
+            member: obj.hash(),
+        },
+    ]
+}
+
+/// Records `topic` as the latest version of its topic. The topic itself, its
+/// history entry, a `TopicVersion` linking it to the previous version and the
+/// header pointing at it are written in one transaction,
+/// which only goes through if `header:{id}` still holds `previous` (the raw
+/// header the change was based on, `None` for a new topic).
+///
+/// Fails with `DumpError::Conflict` if the header moved on in the meantime, in
+/// which case nothing was written.
+pub async fn commit_topic(
+    topic: &Topic,
+    previous: Option<&[u8]>,
+    change: &Change,
+    store: &dyn Store,
+) -> Result<(), DumpError> {
+    commit(topic, previous, None, change, Vec::new(), store).await
+}
+
+/// Commits `topic` as the first version of a new topic, starting out in `state`.
+pub async fn commit_new_topic(
+    topic: &Topic,
+    state: State,
+    change: &Change,
+    store: &dyn Store,
+) -> Result<(), DumpError> {
+    commit(topic, None, Some(state), change, Vec::new(), store).await
+}
+
+/// Commits `topic`, the first version of a new topic forked off another one,
+/// together with the `Fork` recording where it came from.
+pub async fn commit_fork(
+    topic: &Topic,
+    fork: &Fork,
+    change: &Change,
+    store: &dyn Store,
+) -> Result<(), DumpError> {
+    let mut lineage = writes(fork);
+    lineage.push(Write::SetAdd {
+        set: format!("children:{}", fork.parent),
+        member: fork.id.to_string(),
+    });
+
+    commit(topic, None, None, change, lineage, store).await
+}
+
+/// `commit_topic`, with `extra` writes going into the same transaction. The
+/// header keeps the state and deadlines of `previous` unless `state` says
+/// otherwise, a new topic is open by default.
+///
+/// This is where states and deadlines are enforced: fails with
+/// `DumpError::State` if the topic's state doesn't allow `change` or its
+/// voting already ended.
+async fn commit(
+    topic: &Topic,
+    previous: Option<&[u8]>,
+    state: Option<State>,
+    change: &Change,
+    extra: Vec<Write>,
+    store: &dyn Store,
+) -> Result<(), DumpError> {
+    let previous_header = match previous {
+        Some(slice) => Some(serde_json::from_slice::<TopicHeader>(slice)?),
+        None => None,
+    };
+    if let Some(h) = &previous_header {
+        if !h.state.allows(change.operation) {
+            return Err(DumpError::State(format!(
+                "topic {} is {:?}, it doesn't take {:?}",
+                topic.id, h.state, change.operation
+            )));
+        }
+        // the background task may not have closed it yet, late edits are refused all the same
+        if h.past_deadline() {
+            return Err(DumpError::State(format!(
+                "voting on topic {} ended at {}",
+                topic.id,
+                h.closes_at.expect("past a deadline").to_rfc3339()
+            )));
+        }
+    }
+
+    let hash = topic.hash();
+
+    // the header keeps what it knew besides hash, title and version (state, deadlines)
+    let mut header = match previous_header {
+        Some(h) => TopicHeader {
+            hash: hash.to_string(),
+            title: topic.title.to_string(),
+            ..h
+        },
+        None => TopicHeader::new(&topic.id, &hash, &topic.title, None),
+    };
+    let version = TopicVersion::new(&hash, header.version.as_deref());
+    header.version = Some(version.hash());
+    if let Some(state) = state {
+        header.state = state;
+    }
+
+    let mut transaction = writes(topic);
+    transaction.push(Write::ListPush {
+        list: format!("history:{}", topic.id),
+        value: HistoryEntry::new(&hash, change).json(),
+    });
+    transaction.extend(writes(&version));
+    transaction.extend(writes(&header));
+    transaction.extend(extra);
+
+    if store
+        .transaction(&header.domain(), previous, transaction)
+        .await?
+    {
+        return Ok(());
+    }
+
+    let current: TopicHeader = get_object(&topic.id.to_string(), "header", store).await?;
+    Err(DumpError::Conflict { hash: current.hash })
+}
+
 pub async fn push_representative(
     id: &str,
     info: &RepresentativeInfo,
-    redis: &web::Data<Addr<RedisActor>>,
-) -> Option<i64> {
+    store: &dyn Store,
+) -> Result<i64, DumpError> {
     let json_data = serde_json::to_string(&info).expect("info should be serializable");
 
-    let push = redis
-        .send(Command(resp_array![
-            "HSET",
-            "reps",
-            id.to_string(),
-            json_data
-        ]))
-        .await;
-    if let Ok(Ok(Value::Integer(x))) = push {
-        Some(x)
-    } else {
-        None
-    }
+    Ok(store.hash_set("reps", id, json_data.as_bytes()).await?)
 }
 
-pub async fn get_representatives(redis: &web::Data<Addr<RedisActor>>) -> Option<Vec<String>> {
-    let get_reps = redis.send(Command(resp_array!["HKEYS", "reps"])).await;
-
-    if let Ok(Ok(Value::Array(r))) = get_reps {
-        let reps: Vec<String> = r
-            .iter()
-            .map(|v| match v {
-                Value::BulkString(x) => String::from_utf8(x.to_owned()).unwrap().to_owned(),
-                _ => "".to_string(),
-            })
-            .filter(|string| string != &("".to_string()))
-            .collect();
-        Some(reps)
-    } else {
-        None
-    }
+pub async fn get_representatives(store: &dyn Store) -> Result<Vec<String>, DumpError> {
+    Ok(store.hash_keys("reps").await?)
 }
 
 pub async fn get_representative(
     id: &str,
-    redis: &web::Data<Addr<RedisActor>>,
-) -> Option<RepresentativeInfo> {
-    let get_rep = redis.send(Command(resp_array!["HGET", "reps", id])).await;
-
-    if let Ok(Ok(Value::BulkString(r))) = get_rep {
-        let rep: RepresentativeInfo =
-            serde_json::from_slice(&r).expect("Rep Info should be serializable");
-        Some(rep)
-    } else {
-        None
-    }
+    store: &dyn Store,
+) -> Result<RepresentativeInfo, DumpError> {
+    let rep = store
+        .hash_get("reps", id)
+        .await?
+        .ok_or_else(|| DumpError::NotFound(format!("representative {}", id)))?;
+
+    Ok(serde_json::from_slice(&rep)?)
 }
 
-pub async fn push_history(
-    id: &Uuid,
-    hash: &str,
-    redis: &web::Data<Addr<RedisActor>>,
-) -> Option<i64> {
+/// Newest entry first.
+pub async fn get_history(id: &str, store: &dyn Store) -> Result<Vec<HistoryEntry>, DumpError> {
     let domain = format!("history:{}", id);
-    let push = redis
-        .send(Command(resp_array!["RPUSH", &domain, hash]))
-        .await;
-
-    if let Ok(Ok(Value::Integer(x))) = push {
-        Some(x)
-    } else {
-        None
+    let history = store.list_range(&domain, 0, -1).await?;
+
+    Ok(history
+        .iter()
+        .rev()
+        .map(|e| HistoryEntry::parse(e))
+        .collect())
+}
+
+/// Moves topic `id` to `state`, provided its header is still `base`. Only the
+/// header changes, no new version is made. Closing a topic also computes and
+/// stores the result of its final version, in the same transaction.
+pub async fn set_state(
+    base: &[u8],
+    state: State,
+    store: &dyn Store,
+) -> Result<TopicHeader, DumpError> {
+    let mut header: TopicHeader = serde_json::from_slice(base)?;
+
+    if !header.state.can_become(state) {
+        return Err(DumpError::State(format!(
+            "topic {} can't go from {:?} to {:?}",
+            header.id, header.state, state
+        )));
+    }
+
+    header.state = state;
+    let mut transaction = Vec::new();
+    if state == State::Closed {
+        header.closed_at = Some(Utc::now());
+
+        let topic: Topic = get_object(&header.hash, "topic", store).await?;
+        let result = TopicCalculationResult::compute(&header.hash, &topic);
+        transaction.extend(writes(&result));
     }
+    transaction.extend(writes(&header));
+
+    write_header(header, base, transaction, store).await
 }
 
-pub async fn get_history(id: &str, redis: &web::Data<Addr<RedisActor>>) -> Option<Vec<String>> {
-    let domain = format!("history:{}", id);
-    let history = redis
-        .send(Command(resp_array!["LRANGE", &domain, "0", "-1"]))
-        .await;
-
-    if let Ok(Ok(Value::Array(hs))) = history {
-        let hashes: Vec<String> = hs
-            .iter()
-            .map(|v| match v {
-                Value::BulkString(x) => String::from_utf8(x.to_owned()).unwrap().to_owned(),
-                _ => "".to_string(),
-            })
-            .filter(|string| string != &("".to_string()))
-            .rev()
-            .collect();
-        Some(hashes)
-    } else {
-        None
+/// Sets when topic `id` opens and closes by itself, provided its header is
+/// still `base`. `None` removes a deadline.
+pub async fn set_schedule(
+    base: &[u8],
+    opens_at: Option<DateTime<Utc>>,
+    closes_at: Option<DateTime<Utc>>,
+    store: &dyn Store,
+) -> Result<TopicHeader, DumpError> {
+    let mut header: TopicHeader = serde_json::from_slice(base)?;
+
+    match header.state {
+        State::Draft => {}
+        State::Open if opens_at.is_none() => {}
+        State::Open => {
+            return Err(DumpError::State(format!(
+                "topic {} is already open",
+                header.id
+            )))
+        }
+        state => {
+            return Err(DumpError::State(format!(
+                "topic {} is {:?}, its schedule is over",
+                header.id, state
+            )))
+        }
     }
+    match (&opens_at, &closes_at) {
+        (Some(opens), Some(closes)) if closes <= opens => {
+            return Err(DumpError::Validation(
+                "closes_at should be after opens_at".to_string(),
+            ))
+        }
+        // nothing would ever open it
+        (None, Some(_)) if header.state == State::Draft => {
+            return Err(DumpError::Validation(
+                "a draft closing by itself needs opens_at too".to_string(),
+            ))
+        }
+        _ => {}
+    }
+
+    header.opens_at = opens_at;
+    header.closes_at = closes_at;
+    let transaction = writes(&header);
+
+    write_header(header, base, transaction, store).await
+}
+
+/// Runs `transaction` if `header:{id}` still holds `base`, or tells where it is now.
+async fn write_header(
+    header: TopicHeader,
+    base: &[u8],
+    transaction: Vec<Write>,
+    store: &dyn Store,
+) -> Result<TopicHeader, DumpError> {
+    if store
+        .transaction(&header.domain(), Some(base), transaction)
+        .await?
+    {
+        return Ok(header);
+    }
+
+    let current: TopicHeader = get_object(&header.id.to_string(), "header", store).await?;
+    Err(DumpError::Conflict { hash: current.hash })
+}
+
+/// `limit` entries of `id`'s history, newest first, skipping the `offset` newest.
+/// Only that part of the list is read.
+pub async fn get_history_page(
+    id: &str,
+    offset: usize,
+    limit: usize,
+    store: &dyn Store,
+) -> Result<Vec<HistoryEntry>, DumpError> {
+    if limit == 0 {
+        return Ok(Vec::new());
+    }
+
+    // no list gets anywhere near i64::MAX entries, past it is just as empty
+    let offset = i64::try_from(offset).unwrap_or(i64::MAX);
+    let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+
+    // counted from the end of the list, where the newest entries are
+    let domain = format!("history:{}", id);
+    let (start, stop) = (-offset.saturating_add(limit), -offset - 1);
+    let history = store.list_range(&domain, start, stop).await?;
+
+    Ok(history
+        .iter()
+        .rev()
+        .map(|e| HistoryEntry::parse(e))
+        .collect())
+}
+
+pub async fn count_history(id: &str, store: &dyn Store) -> Result<i64, DumpError> {
+    Ok(store.list_len(&format!("history:{}", id)).await?)
 }
 
+/// The entry of `id`'s history that was current at `at`, i.e. the newest one
+/// committed at or before it. Entries from before history had timestamps can't
+/// be placed in time and are never picked.
+pub async fn version_at(
+    id: &str,
+    at: &DateTime<Utc>,
+    store: &dyn Store,
+) -> Result<HistoryEntry, DumpError> {
+    get_history(id, store)
+        .await?
+        .into_iter()
+        .find(|e| e.at.map_or(false, |t| &t <= at))
+        .ok_or_else(|| {
+            DumpError::NotFound(format!("version of topic {} at {}", id, at.to_rfc3339()))
+        })
+}
+
+/// The raw bytes under `{domain_prefix}:{id}`, `None` if there is nothing there.
 pub async fn get_slice(
     id: &str,
     domain_prefix: &str,
-    redis: &web::Data<Addr<RedisActor>>,
-) -> Option<Vec<u8>> {
+    store: &dyn Store,
+) -> Result<Option<Vec<u8>>, DumpError> {
     let domain = format!("{}:{}", domain_prefix, id);
 
-    let obj = redis.send(Command(resp_array!["GET", &domain])).await;
+    Ok(store.get(&domain).await?)
+}
 
-    if let Ok(Ok(Value::BulkString(x))) = obj {
-        Some(x)
-    } else {
-        None
-    }
+/// Like `get_slice`, but deserialized, and a missing key is a `DumpError::NotFound`.
+pub async fn get_object<T: DeserializeOwned>(
+    id: &str,
+    domain_prefix: &str,
+    store: &dyn Store,
+) -> Result<T, DumpError> {
+    let slice = get_slice(id, domain_prefix, store)
+        .await?
+        .ok_or_else(|| DumpError::NotFound(format!("{}:{}", domain_prefix, id)))?;
+
+    Ok(serde_json::from_slice(&slice)?)
 }
 
-pub async fn get_list(domain: &str, redis: &web::Data<Addr<RedisActor>>) -> Option<Vec<String>> {
+pub async fn get_list(domain: &str, store: &dyn Store) -> Result<Vec<String>, DumpError> {
     let plural = format!("{}s", domain);
-    let set = redis.send(Command(resp_array!["SMEMBERS", &plural])).await;
-    match set {
-        Ok(Ok(Value::Array(x))) => {
-            let mut result = Vec::new();
-            for e in x {
-                if let Value::BulkString(x) = e {
-                    let id = String::from_utf8(x).expect("id should be utf-8");
-                    result.push(id);
-                }
-            }
-            Some(result)
-        }
-        _ => return None,
+    Ok(store.set_members(&plural).await?)
+}
+
+/// Where `id` was forked from, `None` if it wasn't.
+pub async fn get_fork(id: &str, store: &dyn Store) -> Result<Option<Fork>, DumpError> {
+    match get_slice(id, "fork", store).await? {
+        Some(slice) => Ok(Some(serde_json::from_slice(&slice)?)),
+        None => Ok(None),
     }
 }
+
+/// Ids of the topics forked off `id`.
+pub async fn get_children(id: &str, store: &dyn Store) -> Result<Vec<String>, DumpError> {
+    Ok(store.set_members(&format!("children:{}", id)).await?)
+}
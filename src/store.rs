@@ -0,0 +1,131 @@
+use crate::{
+    file_store::FileStore,
+    memory_store::MemoryStore,
+    redis_store::{RedisConfig, RedisStore},
+};
+use async_trait::async_trait;
+use std::{env, fmt, sync::Arc};
+
+#[derive(Debug)]
+pub enum StoreError {
+    /// the backend could not be reached
+    Unavailable(String),
+    /// the backend answered, but not with what we asked for
+    Backend(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Unavailable(mes) => write!(f, "store unavailable: {}", mes),
+            StoreError::Backend(mes) => write!(f, "store error: {}", mes),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+/// A write queued up in a transaction, see `Store::transaction`.
+#[derive(Debug, Clone)]
+pub enum Write {
+    Set {
+        key: String,
+        value: Vec<u8>,
+    },
+    SetAdd {
+        set: String,
+        member: String,
+    },
+    ListPush {
+        list: String,
+        value: String,
+    },
+    HashSet {
+        hash: String,
+        field: String,
+        value: Vec<u8>,
+    },
+}
+
+/// The storage primitives `redis_util` is built on.
+///
+/// These mirror the handful of Redis data types we use (strings, sets, lists and
+/// hashes), so every backend ends up with the same keyspace: `topic:{hash}`,
+/// `header:{id}`, `history:{id}`, `reps` and so on.
+#[async_trait(?Send)]
+pub trait Store: Send + Sync {
+    /// GET
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StoreError>;
+
+    /// SET
+    async fn set(&self, key: &str, value: &[u8]) -> Result<(), StoreError>;
+
+    /// SADD, returns the number of members that were added
+    async fn set_add(&self, set: &str, member: &str) -> Result<i64, StoreError>;
+
+    /// SMEMBERS
+    async fn set_members(&self, set: &str) -> Result<Vec<String>, StoreError>;
+
+    /// RPUSH, returns the length of the list after the push
+    async fn list_push(&self, list: &str, value: &str) -> Result<i64, StoreError>;
+
+    /// LRANGE, `start` and `stop` are inclusive and may be negative
+    async fn list_range(
+        &self,
+        list: &str,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<String>, StoreError>;
+
+    /// LLEN, 0 for a list that doesn't exist
+    async fn list_len(&self, list: &str) -> Result<i64, StoreError>;
+
+    /// HSET, returns the number of fields that were added
+    async fn hash_set(&self, hash: &str, field: &str, value: &[u8]) -> Result<i64, StoreError>;
+
+    /// HGET
+    async fn hash_get(&self, hash: &str, field: &str) -> Result<Option<Vec<u8>>, StoreError>;
+
+    /// HKEYS
+    async fn hash_keys(&self, hash: &str) -> Result<Vec<String>, StoreError>;
+
+    /// Applies all `writes` or none of them, and only if `watch` still holds
+    /// `expected` (`None` meaning the key must not exist). Returns `false` when
+    /// the key has moved on and nothing was written.
+    async fn transaction(
+        &self,
+        watch: &str,
+        expected: Option<&[u8]>,
+        writes: Vec<Write>,
+    ) -> Result<bool, StoreError>;
+
+    /// Checks that the backend is reachable.
+    async fn ping(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
+
+/// Picks the backend from `STORE_BACKEND` ("redis", "file" or "memory", defaults to "redis").
+/// The file backend writes to `STORE_PATH`, and compacts its log first when `compact` is
+/// set. Only the server does that, tools leave the log alone.
+pub fn from_env(compact: bool) -> Arc<dyn Store> {
+    let backend: String = env::var("STORE_BACKEND").unwrap_or("redis".to_string());
+
+    match backend.as_ref() {
+        "memory" => {
+            log::warn!("using the in-memory store, nothing will be persisted");
+            Arc::new(MemoryStore::default())
+        }
+        "file" => {
+            let path: String = env::var("STORE_PATH").unwrap_or("dump.ndjson".to_string());
+            log::info!("using the file store at {}", path);
+            let store = FileStore::open(&path).expect("could not open the store file");
+            if compact {
+                store.compact().expect("could not compact the store file");
+            }
+            Arc::new(store)
+        }
+        "redis" => Arc::new(RedisStore::start(RedisConfig::from_env())),
+        other => panic!("unknown STORE_BACKEND: {}", other),
+    }
+}
@@ -0,0 +1,149 @@
+//! Checks on votes before they are stored.
+//!
+//! A vote map may only point at policies and delegates of its topic, never at
+//! the voter themselves, with finite non-negative weights that don't all add
+//! up to zero. An empty map is fine, that's abstaining.
+
+use crate::{
+    error::{DumpError, FieldError},
+    model::Topic,
+};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+/// Problems with `voter`'s `vote` on `topic`, each under `{prefix}.{target}`
+/// (or just `prefix` for the map as a whole).
+pub fn check_vote(
+    topic: &Topic,
+    voter: &Uuid,
+    vote: &BTreeMap<Uuid, f64>,
+    prefix: &str,
+) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+
+    for (target, weight) in vote {
+        let field = format!("{}.{}", prefix, target);
+
+        if target == voter {
+            errors.push(FieldError::new(&field, "can't delegate to yourself"));
+        } else if !topic.policies.contains_key(target) && !topic.delegates.contains_key(target) {
+            errors.push(FieldError::new(
+                &field,
+                "not a policy or delegate of this topic",
+            ));
+        }
+
+        if !weight.is_finite() {
+            errors.push(FieldError::new(&field, "weight should be a finite number"));
+        } else if *weight < 0.0 {
+            errors.push(FieldError::new(&field, "weight can't be negative"));
+        }
+    }
+
+    let finite = vote.values().all(|w| w.is_finite());
+    if !vote.is_empty() && finite && vote.values().sum::<f64>() <= 0.0 {
+        errors.push(FieldError::new(prefix, "weights add up to nothing"));
+    }
+
+    errors
+}
+
+/// Every vote of `topic`, under `votes.{voter}`.
+pub fn check_topic(topic: &Topic) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+
+    for (voter, vote) in &topic.votes {
+        let prefix = format!("votes.{}", voter);
+        if !topic.delegates.contains_key(voter) {
+            errors.push(FieldError::new(
+                &prefix,
+                "voter is not a delegate of this topic",
+            ));
+        }
+        errors.extend(check_vote(topic, voter, vote, &prefix));
+    }
+
+    errors
+}
+
+/// `Ok` for no errors, `DumpError::Fields` otherwise.
+pub fn ok(errors: Vec<FieldError>) -> Result<(), DumpError> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(DumpError::Fields(errors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vote(targets: &[(Uuid, f64)]) -> BTreeMap<Uuid, f64> {
+        targets.iter().cloned().collect()
+    }
+
+    fn fields(errors: &[FieldError]) -> Vec<&str> {
+        errors.iter().map(|e| e.field.as_str()).collect()
+    }
+
+    #[test]
+    fn fine_votes_pass() {
+        let mut topic = Topic::new("t", "d");
+        let bread = topic.add_policy("bread");
+        let (me, friend) = (Uuid::new_v4(), Uuid::new_v4());
+        topic.set_delegate(&friend, "friend");
+
+        assert!(check_vote(&topic, &me, &vote(&[(bread, 2.0), (friend, 0.5)]), "vote").is_empty());
+        // abstaining
+        assert!(check_vote(&topic, &me, &BTreeMap::new(), "vote").is_empty());
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let mut topic = Topic::new("t", "d");
+        let bread = topic.add_policy("bread");
+        let rice = topic.add_policy("rice");
+        let (me, stranger) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let errors = check_vote(
+            &topic,
+            &me,
+            &vote(&[(me, 1.0), (stranger, 1.0), (bread, -1.0), (rice, f64::NAN)]),
+            "vote",
+        );
+        let mut got = fields(&errors);
+        got.sort();
+        let mut expected = vec![
+            format!("vote.{}", me),
+            format!("vote.{}", stranger),
+            format!("vote.{}", bread),
+            format!("vote.{}", rice),
+        ];
+        expected.sort();
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn zero_sum_is_refused() {
+        let mut topic = Topic::new("t", "d");
+        let bread = topic.add_policy("bread");
+
+        let errors = check_vote(&topic, &Uuid::new_v4(), &vote(&[(bread, 0.0)]), "vote");
+        assert_eq!(fields(&errors), vec!["vote"]);
+    }
+
+    #[test]
+    fn topics_need_their_voters_as_delegates() {
+        let mut topic = Topic::new("t", "d");
+        let bread = topic.add_policy("bread");
+        let voter = Uuid::new_v4();
+        topic.set_vote(&voter, vote(&[(bread, 1.0)]));
+
+        let errors = check_topic(&topic);
+        assert_eq!(fields(&errors), vec![format!("votes.{}", voter)]);
+
+        topic.set_delegate(&voter, "v");
+        assert!(ok(check_topic(&topic)).is_ok());
+    }
+}
@@ -0,0 +1,322 @@
+//! The `/db` API on a `MemoryStore`.
+
+use actix_web::{
+    http::{header, StatusCode},
+    test, web, App,
+};
+use dump::{error::DumpError, handlers::*, memory_store::MemoryStore, store::Store};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use uuid::Uuid;
+
+macro_rules! app {
+    () => {{
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::default());
+        test::init_service(
+            App::new()
+                .app_data(web::Data::from(store))
+                .app_data(
+                    web::JsonConfig::default()
+                        .error_handler(|err, _req| DumpError::Validation(err.to_string()).into()),
+                )
+                .app_data(
+                    web::QueryConfig::default()
+                        .error_handler(|err, _req| DumpError::Validation(err.to_string()).into()),
+                )
+                .app_data(
+                    web::PathConfig::default()
+                        .error_handler(|err, _req| DumpError::Validation(err.to_string()).into()),
+                )
+                .service(
+                    web::scope("/db")
+                        .service(history)
+                        .service(history_count)
+                        .service(dump_result)
+                        .service(get_result)
+                        .service(update_vote)
+                        .service(withdraw_voter)
+                        .service(add_policy)
+                        .service(batch_update)
+                        .service(update_field)
+                        .service(make_new_topic)
+                        .service(post_topic_raw)
+                        .service(set_state)
+                        .service(set_schedule)
+                        .service(get_header)
+                        .service(get_topic_by_id),
+                ),
+        )
+        .await
+    }};
+}
+
+macro_rules! call {
+    ($app:expr, $req:expr) => {{
+        let res = test::call_service(&mut $app, $req.to_request()).await;
+        let status = res.status();
+        let etag = res
+            .headers()
+            .get(header::ETAG)
+            .map(|v| v.to_str().unwrap().to_string());
+        let body: Value = test::read_body_json(res).await;
+        (status, body, etag)
+    }};
+}
+
+fn post(uri: &str, body: Value) -> test::TestRequest {
+    test::TestRequest::post().uri(uri).set_json(&body)
+}
+
+fn get(uri: &str) -> test::TestRequest {
+    test::TestRequest::get().uri(uri)
+}
+
+macro_rules! new_topic {
+    ($app:expr, $state:expr) => {{
+        let body = json!({"title": "lunch", "description": "what to eat", "state": $state});
+        let (status, body, _) = call!($app, post("/db/topic/new/", body));
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        body["id"].as_str().unwrap().to_string()
+    }};
+}
+
+#[actix_rt::test]
+async fn edits_need_the_current_hash() {
+    let mut app = app!();
+    let id = new_topic!(app, "open");
+
+    let (status, _, etag) = call!(app, get(&format!("/db/topic/{}/", id)));
+    assert_eq!(status, StatusCode::OK);
+    let etag = etag.unwrap();
+
+    let title = format!("/db/topic/update/{}/title/", id);
+    let (status, _, _) = call!(
+        app,
+        post(&title, json!("dinner")).header(header::IF_MATCH, etag.as_str())
+    );
+    assert_eq!(status, StatusCode::OK);
+
+    // the same hash again is stale now
+    let (status, body, _) = call!(
+        app,
+        post(&title, json!("breakfast")).header(header::IF_MATCH, etag.as_str())
+    );
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"], "conflict");
+
+    let (_, current, _) = call!(app, get(&format!("/db/header/{}", id)));
+    assert_eq!(current["title"], "dinner");
+    assert_eq!(body["hash"], current["hash"]);
+
+    let (status, body, _) = call!(app, post(&title, json!("dinner")));
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["mes"], "no change in data");
+}
+
+#[actix_rt::test]
+async fn missing_topics_are_404() {
+    let mut app = app!();
+
+    let (status, body, _) = call!(app, get(&format!("/db/topic/{}/", Uuid::new_v4())));
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["status"], "error");
+}
+
+#[actix_rt::test]
+async fn malformed_bodies_are_validation_errors() {
+    let mut app = app!();
+
+    let (status, body, _) = call!(app, post("/db/topic/new/", json!({"title": 1})));
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "validation");
+
+    let id = new_topic!(app, "open");
+    let uris = vec![
+        format!("/db/topic/{}/?at=garbage", id),
+        format!("/db/history/{}/?offset=-1", id),
+    ];
+    for uri in &uris {
+        let (status, body, _) = call!(app, get(uri));
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+        assert_eq!(body["error"], "validation");
+    }
+}
+
+#[actix_rt::test]
+async fn votes_are_checked_field_by_field() {
+    let mut app = app!();
+    let id = new_topic!(app, "open");
+    let voter = Uuid::new_v4();
+    let stranger = Uuid::new_v4();
+
+    let vote = json!({"id": voter, "name": "v", "vote": {stranger.to_string(): 1.0}});
+    let (status, body, _) = call!(
+        app,
+        post(&format!("/db/topic/update/{}/delegate/", id), vote)
+    );
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["fields"][0]["field"], format!("vote.{}", stranger));
+
+    let topic = json!({
+        "id": Uuid::new_v4(),
+        "title": "t",
+        "description": "d",
+        "policies": [],
+        "delegates": [],
+        "votes": {voter.to_string(): [[voter, 1.0]]},
+    });
+    let (status, body, _) = call!(app, post("/db/topic/raw/", topic));
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["fields"].as_array().unwrap().len(), 2);
+}
+
+#[actix_rt::test]
+async fn history_is_paged_newest_first() {
+    let mut app = app!();
+    let id = new_topic!(app, "open");
+    for title in &["a", "b", "c"] {
+        let uri = format!("/db/topic/update/{}/title/", id);
+        let (status, _, _) = call!(app, post(&uri, json!(title)));
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    let (_, body, _) = call!(app, get(&format!("/db/history/{}/count/", id)));
+    assert_eq!(body["count"], 4);
+
+    let (_, all, _) = call!(app, get(&format!("/db/history/{}/", id)));
+    let all = all.as_array().unwrap().clone();
+    assert_eq!(all.len(), 4);
+    assert_eq!(all[3]["operation"], "created");
+    assert_eq!(all[0]["operation"], "title_changed");
+
+    let (_, page, _) = call!(app, get(&format!("/db/history/{}/?offset=1&limit=2", id)));
+    assert_eq!(page.as_array().unwrap(), &all[1..3].to_vec());
+
+    let (_, page, _) = call!(app, get(&format!("/db/history/{}/?offset=10", id)));
+    assert!(page.as_array().unwrap().is_empty());
+
+    let uri = format!("/db/history/{}/?offset={}&limit={}", id, u64::MAX, u64::MAX);
+    let (status, page, _) = call!(app, get(&uri));
+    assert_eq!(status, StatusCode::OK);
+    assert!(page.as_array().unwrap().is_empty());
+}
+
+#[actix_rt::test]
+async fn batches_are_all_or_nothing() {
+    let mut app = app!();
+    let id = new_topic!(app, "open");
+    let uri = format!("/db/topic/update/{}/batch/", id);
+
+    let ops = json!([
+        {"op": "set_title", "title": "new"},
+        {"op": "remove_policy", "policy": Uuid::new_v4()},
+    ]);
+    let (status, _, _) = call!(app, post(&uri, ops));
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (_, current, _) = call!(app, get(&format!("/db/header/{}", id)));
+    assert_eq!(current["title"], "lunch");
+
+    let ops = json!([
+        {"op": "set_title", "title": "new"},
+        {"op": "add_policy", "policy": "bread"},
+    ]);
+    let (status, body, _) = call!(app, post(&uri, ops));
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["added_policies"].as_array().unwrap().len(), 1);
+
+    let (_, entries, _) = call!(app, get(&format!("/db/history/{}/", id)));
+    assert_eq!(entries[0]["operation"], "batch");
+    assert_eq!(entries.as_array().unwrap().len(), 2);
+}
+
+#[actix_rt::test]
+async fn withdrawing_records_who_did_it() {
+    let mut app = app!();
+    let id = new_topic!(app, "open");
+    let (voter, organiser) = (Uuid::new_v4(), Uuid::new_v4());
+
+    let (_, _, _) = call!(
+        app,
+        post(&format!("/db/topic/update/{}/policy/", id), json!("bread"))
+    );
+    let vote = json!({"id": voter, "name": "v", "vote": {}});
+    let (status, _, _) = call!(
+        app,
+        post(&format!("/db/topic/update/{}/delegate/", id), vote)
+    );
+    assert_eq!(status, StatusCode::OK);
+
+    let uri = format!("/db/topic/update/{}/delegate/{}/withdraw/", id, voter);
+    let (status, _, _) = call!(
+        app,
+        post(&uri, json!(null)).header("X-Voter", organiser.to_string())
+    );
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, entries, _) = call!(app, get(&format!("/db/history/{}/", id)));
+    assert_eq!(entries[0]["operation"], "voter_withdrawn");
+    assert_eq!(entries[0]["actor"], json!(organiser));
+    assert_eq!(entries[0]["subject"], json!(voter));
+}
+
+#[actix_rt::test]
+async fn drafts_take_no_votes_and_closed_topics_no_edits() {
+    let mut app = app!();
+    let id = new_topic!(app, "draft");
+
+    let vote = json!({"id": Uuid::new_v4(), "name": "v", "vote": {}});
+    let (status, body, _) = call!(
+        app,
+        post(&format!("/db/topic/update/{}/delegate/", id), vote.clone())
+    );
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"], "state");
+
+    let state = format!("/db/topic/state/{}/", id);
+    let (status, _, _) = call!(app, post(&state, json!("open")));
+    assert_eq!(status, StatusCode::OK);
+    let (status, _, _) = call!(
+        app,
+        post(&format!("/db/topic/update/{}/delegate/", id), vote)
+    );
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _, _) = call!(app, post(&state, json!("closed")));
+    assert_eq!(status, StatusCode::OK);
+    let (status, _, _) = call!(
+        app,
+        post(&format!("/db/topic/update/{}/title/", id), json!("late"))
+    );
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, _, _) = call!(app, post(&state, json!("open")));
+    assert_eq!(status, StatusCode::CONFLICT);
+}
+
+#[actix_rt::test]
+async fn closing_stores_the_result() {
+    let mut app = app!();
+    let id = new_topic!(app, "draft");
+
+    // nothing would ever open it
+    let schedule = json!({"opens_at": null, "closes_at": "2030-01-01T00:00:00Z"});
+    let (status, _, _) = call!(app, post(&format!("/db/topic/schedule/{}/", id), schedule));
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let state = format!("/db/topic/state/{}/", id);
+    let (status, _, _) = call!(app, post(&state, json!("open")));
+    assert_eq!(status, StatusCode::OK);
+    let (status, body, _) = call!(app, post(&state, json!("closed")));
+    assert_eq!(status, StatusCode::OK);
+    let hash = body["header"]["hash"].as_str().unwrap().to_string();
+
+    let (status, result, _) = call!(app, get(&format!("/db/result/{}/", hash)));
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(result["topic_hash"], json!(hash));
+    assert_eq!(result["computed"], true);
+
+    let (status, body, _) = call!(app, post(&format!("/db/result/{}/", hash), json!([{}, {}])));
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"], "state");
+    let (_, kept, _) = call!(app, get(&format!("/db/result/{}/", hash)));
+    assert_eq!(kept, result);
+}